@@ -0,0 +1,130 @@
+extern crate sdl2;
+
+use sdl2::rect::Rect;
+
+use std::cmp;
+
+use game::{Map, View};
+
+/* Largest cell size reachable by zooming in, in pixels */
+const MAX_CELL: u32 = 256;
+
+/* Follows the player on maps that don't fit in the window. When the map
+ * fits at the current zoom level, it is scaled and centered instead */
+pub struct Camera {
+    base: u32,                  /* configured cell size */
+    margin: i32,                /* cells kept between the player and the border */
+    zoom: u32,                  /* requested cell size, smaller maps fit */
+    cell: u32,                  /* cell size of the last view */
+    offset: (i32, i32),         /* top left corner of the view, in board pixels */
+    area: (i32, i32),           /* size of the last area */
+    followed: Option<(i32, i32)> /* player position the view was adjusted to */
+}
+
+impl Camera {
+    pub fn new(base: u32, margin: u32) -> Camera {
+        Camera {
+            base,
+            margin: margin as i32,
+            zoom: base,
+            cell: base,
+            offset: (0, 0),
+            area: (0, 0),
+            followed: None
+        }
+    }
+
+    pub fn cell_size(&self) -> u32 {
+        self.cell
+    }
+
+    /* Change the cell size, keeping the center of the view in place */
+    fn set_zoom(&mut self, cell: u32) {
+        let cell = cell.clamp(1, MAX_CELL);
+        let (w, h) = self.area;
+        let (ox, oy) = self.offset;
+        let scale = |o: i32, half: i32| {
+            ((o + half) as i64 * cell as i64 / self.cell as i64) as i32 - half
+        };
+        self.offset = (scale(ox, w / 2), scale(oy, h / 2));
+        self.zoom = cell;
+        self.cell = cell;
+    }
+
+    pub fn zoom_in(&mut self, integer: bool) {
+        let cell = if integer && self.cell >= self.base {
+            (self.cell / self.base + 1) * self.base
+        } else if integer {
+            self.base
+        } else {
+            cmp::max(self.cell * 5 / 4, self.cell + 1)
+        };
+        self.set_zoom(cell)
+    }
+
+    /* Can't zoom out further than the size fitting the whole map */
+    pub fn zoom_out(&mut self, integer: bool) {
+        let cell = if integer && self.cell > self.base {
+            (self.cell - 1) / self.base * self.base
+        } else {
+            self.cell * 4 / 5
+        };
+        self.set_zoom(cell)
+    }
+
+    /* Move the view by (dx, dy) pixels. It stays there until the player
+     * leaves the visible part of the map */
+    pub fn pan(&mut self, dx: i32, dy: i32) {
+        self.offset = (self.offset.0 + dx, self.offset.1 + dy);
+    }
+
+    pub fn view(&mut self, map: &Map, area: Rect, integer: bool) -> View {
+        self.area = (area.width() as i32, area.height() as i32);
+        let fit = map.fit(area, integer);
+        if self.zoom <= fit.cell {
+            self.cell = fit.cell;
+            return fit
+        }
+        let cell = self.zoom;
+        self.cell = cell;
+
+        let cs = cell as i32;
+        let (w, h) = self.area;
+        let (px, py) = map.player();
+        if self.followed != Some((px, py)) {
+            self.followed = Some((px, py));
+            self.offset = (follow(self.offset.0, px * cs, cs, self.margin, w),
+                           follow(self.offset.1, py * cs, cs, self.margin, h));
+        }
+
+        let (x, ox) = place(area.x(), self.offset.0, map.width * cs, w);
+        let (y, oy) = place(area.y(), self.offset.1, map.height * cs, h);
+        self.offset = (ox, oy);
+        View { x, y, cell }
+    }
+}
+
+/* Move offset along one axis so that the player at position pos (both in
+ * board pixels) is at least margin cells away from the border */
+fn follow(offset: i32, pos: i32, cs: i32, margin: i32, size: i32) -> i32 {
+    /* the margin can't be more than half of the view */
+    let m = cmp::max(0, cmp::min(margin, (size / cs - 1) / 2)) * cs;
+    if pos - m < offset {
+        pos - m
+    } else if pos + cs + m > offset + size {
+        pos + cs + m - size
+    } else {
+        offset
+    }
+}
+
+/* Screen position of the board along one axis, and the clamped offset.
+ * Boards smaller than the view are centered */
+fn place(origin: i32, offset: i32, board: i32, size: i32) -> (i32, i32) {
+    if board <= size {
+        (origin + (size - board) / 2, 0)
+    } else {
+        let offset = cmp::max(0, cmp::min(offset, board - size));
+        (origin - offset, offset)
+    }
+}
@@ -3,6 +3,60 @@ extern crate sdl2;
 
 use sdl2::keyboard::*;
 use std::fs;
+use std::fmt;
+
+use theme;
+use tileset;
+
+/* Known entries, used to warn about typos in configuration files */
+const CONFIG_KEYS: [&str; 15] = ["undo-level", "replay-speed", "key-bindings",
+                                 "theme", "shape-glyphs", "tileset",
+                                 "cell-size", "integer-scaling", "camera-margin",
+                                 "hud", "animation-duration", "scoreboard",
+                                 "replays", "repeat-delay", "repeat-rate"];
+
+/* Actions that can be bound to a key, as named in the configuration */
+pub const ACTIONS: [&str; 17] = ["up", "down", "left", "right", "undo",
+                                 "quit", "reset-level", "settings",
+                                 "fullscreen", "zoom-in", "zoom-out",
+                                 "pan-up", "pan-down", "pan-left", "pan-right",
+                                 "hud", "switch-player"];
+
+pub enum ConfigError {
+    Io { path: String, reason: String },
+    Parse { path: String, reason: String },
+    Write { path: String, reason: String },
+    Missing { field: String, expected: &'static str },
+    InvalidType { field: String, expected: &'static str, found: String },
+    InvalidValue { field: String, expected: &'static str, found: String },
+    UnknownKey { field: String },
+    Conflict { field: String, other: String, key: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io { ref path, ref reason } =>
+                write!(f, "Can't read configuration file '{}': {}", path, reason),
+            ConfigError::Parse { ref path, ref reason } =>
+                write!(f, "Can't parse configuration file '{}': {}", path, reason),
+            ConfigError::Write { ref path, ref reason } =>
+                write!(f, "Can't write configuration file '{}': {}", path, reason),
+            ConfigError::Missing { ref field, expected } =>
+                write!(f, "Missing '{}' entry, expected {}", field, expected),
+            ConfigError::InvalidType { ref field, expected, ref found } =>
+                write!(f, "Invalid '{}' entry: expected {}, found {}",
+                       field, expected, found),
+            ConfigError::InvalidValue { ref field, expected, ref found } =>
+                write!(f, "Invalid '{}' value: expected {}, found {}",
+                       field, expected, found),
+            ConfigError::UnknownKey { ref field } =>
+                write!(f, "Unknown configuration entry '{}'", field),
+            ConfigError::Conflict { ref field, ref other, ref key } =>
+                write!(f, "'{}' and '{}' are both bound to '{}'", field, other, key),
+        }
+    }
+}
 
 pub struct KeyBindings {
     pub up: Keycode,
@@ -12,8 +66,42 @@ pub struct KeyBindings {
     pub undo: Keycode,
     pub quit: Keycode,
     pub reset: Keycode,
+    pub settings: Keycode,
+    /* display actions can be left without a key */
+    pub fullscreen: Option<Keycode>,
+    pub zoom_in: Option<Keycode>,
+    pub zoom_out: Option<Keycode>,
+    pub pan_up: Option<Keycode>,
+    pub pan_down: Option<Keycode>,
+    pub pan_left: Option<Keycode>,
+    pub pan_right: Option<Keycode>,
+    pub hud: Option<Keycode>,
+    pub switch: Keycode,
 }
 
+/* Non letter keys, named the same way in both directions. Escape isn't
+ * there: it always pauses or goes back, so it can't be bound */
+const NAMED_KEYS: [(&str, Keycode); 18] = [
+    ("return", Keycode::Return),
+    ("space", Keycode::Space),
+    ("tab", Keycode::Tab),
+    ("minus", Keycode::Minus),
+    ("equals", Keycode::Equals),
+    ("plus", Keycode::Plus),
+    ("f1", Keycode::F1),
+    ("f2", Keycode::F2),
+    ("f3", Keycode::F3),
+    ("f4", Keycode::F4),
+    ("f5", Keycode::F5),
+    ("f6", Keycode::F6),
+    ("f7", Keycode::F7),
+    ("f8", Keycode::F8),
+    ("f9", Keycode::F9),
+    ("f10", Keycode::F10),
+    ("f11", Keycode::F11),
+    ("f12", Keycode::F12),
+];
+
 fn map_keys(entry: &str) -> Option<Keycode> {
     if entry == "arrow-up" {
         Some(Keycode::Up)
@@ -25,17 +113,14 @@ fn map_keys(entry: &str) -> Option<Keycode> {
         Some(Keycode::Right)
     } else if entry == "backspace" {
         Some(Keycode::Backspace)
+    } else if let Some(&(_, k)) = NAMED_KEYS.iter().find(|&&(n, _)| n == entry) {
+        Some(k)
     } else {
-        let ch = {
-            match entry.chars().next() {
-                Some(c) => {
-                    match c.to_lowercase().next() {
-                        Some(d) => d,
-                        None => return None
-                    }
-                },
-                None => return None
-            }
+        /* letters are single characters, anything longer is a typo */
+        let mut chars = entry.chars();
+        let ch = match (chars.next(), chars.next()) {
+            (Some(c), None) => c.to_lowercase().next()?,
+            _ => return None
         };
         match ch {
             'a' => Some(Keycode::A),
@@ -69,8 +154,163 @@ fn map_keys(entry: &str) -> Option<Keycode> {
     }
 }
 
+/* Inverse of map_keys, used when writing the configuration back */
+pub fn key_name(key: Keycode) -> Option<&'static str> {
+    if let Some(&(n, _)) = NAMED_KEYS.iter().find(|&&(_, k)| k == key) {
+        return Some(n)
+    }
+    match key {
+        Keycode::Up => Some("arrow-up"),
+        Keycode::Down => Some("arrow-down"),
+        Keycode::Left => Some("arrow-left"),
+        Keycode::Right => Some("arrow-right"),
+        Keycode::Backspace => Some("backspace"),
+        Keycode::A => Some("a"),
+        Keycode::B => Some("b"),
+        Keycode::C => Some("c"),
+        Keycode::D => Some("d"),
+        Keycode::E => Some("e"),
+        Keycode::F => Some("f"),
+        Keycode::G => Some("g"),
+        Keycode::H => Some("h"),
+        Keycode::I => Some("i"),
+        Keycode::J => Some("j"),
+        Keycode::K => Some("k"),
+        Keycode::L => Some("l"),
+        Keycode::M => Some("m"),
+        Keycode::N => Some("n"),
+        Keycode::O => Some("o"),
+        Keycode::P => Some("p"),
+        Keycode::Q => Some("q"),
+        Keycode::R => Some("r"),
+        Keycode::S => Some("s"),
+        Keycode::T => Some("t"),
+        Keycode::U => Some("u"),
+        Keycode::V => Some("v"),
+        Keycode::W => Some("w"),
+        Keycode::X => Some("x"),
+        Keycode::Y => Some("y"),
+        Keycode::Z => Some("z"),
+        _ => None
+    }
+}
+
+/* Key set by the user for the action name, if any */
+fn read_key(keys: &json::JsonValue, name: &str)
+            -> Result<Option<Keycode>, ConfigError> {
+    let entry = &keys[name];
+    if entry.is_null() {
+        return Ok(None)
+    }
+    let field = format!("key-bindings.{}", name);
+    match entry.as_str() {
+        /* it always pauses or goes back */
+        Some("escape") => Err(ConfigError::InvalidValue {
+            field: field,
+            expected: "a key other than escape",
+            found: entry.dump()
+        }),
+        Some(s) => match map_keys(s) {
+            Some(k) => Ok(Some(k)),
+            None => Err(ConfigError::InvalidValue {
+                field: field,
+                expected: "a key name",
+                found: entry.dump()
+            })
+        },
+        None => Err(ConfigError::InvalidType {
+            field: field,
+            expected: "a string",
+            found: entry.dump()
+        })
+    }
+}
+
+/* Unknown entries are not fatal, but they usually are typos */
+fn check_keys(obj: &json::JsonValue, prefix: &str, known: &[&str],
+              warnings: &mut Vec<ConfigError>) {
+    for (key, _) in obj.entries() {
+        if !known.contains(&key) {
+            let field = if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            warnings.push(ConfigError::UnknownKey { field: field })
+        }
+    }
+}
+
+/* Non negative integer entry, mandatory unless a default is given.
+ * Floats like 2.5 are rejected */
+fn read_uint(config: &json::JsonValue, field: &str, min: u64,
+             default: Option<u64>) -> Result<u64, ConfigError> {
+    let entry = &config[field];
+    if entry.is_null() {
+        return match default {
+            Some(d) => Ok(d),
+            None => Err(ConfigError::Missing {
+                field: field.to_string(),
+                expected: "an integer"
+            })
+        }
+    }
+    let n = match entry.as_f64() {
+        Some(n) => n,
+        None => return Err(ConfigError::InvalidType {
+            field: field.to_string(),
+            expected: "an integer",
+            found: entry.dump()
+        })
+    };
+    if n.fract() != 0.0 || n < min as f64 || n > u32::MAX as f64 {
+        return Err(ConfigError::InvalidValue {
+            field: field.to_string(),
+            expected: if min > 0 { "a positive integer" }
+                      else { "a non-negative integer" },
+            found: entry.dump()
+        })
+    }
+    Ok(n as u64)
+}
+
+/* Optional boolean entry */
+fn read_bool(config: &json::JsonValue, field: &str, default: bool)
+             -> Result<bool, ConfigError> {
+    let entry = &config[field];
+    if entry.is_null() {
+        return Ok(default)
+    }
+    match entry.as_bool() {
+        Some(b) => Ok(b),
+        None => Err(ConfigError::InvalidType {
+            field: field.to_string(),
+            expected: "a boolean",
+            found: entry.dump()
+        })
+    }
+}
+
+/* Optional path entry */
+fn read_path<'a>(config: &'a json::JsonValue, field: &str)
+                 -> Result<Option<&'a str>, ConfigError> {
+    let entry = &config[field];
+    if entry.is_null() {
+        return Ok(None)
+    }
+    match entry.as_str() {
+        Some(p) => Ok(Some(p)),
+        None => Err(ConfigError::InvalidType {
+            field: field.to_string(),
+            expected: "a path",
+            found: entry.dump()
+        })
+    }
+}
+
 impl KeyBindings {
-    pub fn new(config: &json::JsonValue, path: &str) -> KeyBindings {
+    pub fn new(config: &json::JsonValue, warnings: &mut Vec<ConfigError>)
+               -> Result<KeyBindings, ConfigError> {
 
         /* Default bindings */
         let mut kb = KeyBindings {
@@ -81,103 +321,444 @@ impl KeyBindings {
             undo: Keycode::Backspace,
             quit: Keycode::Q,
             reset: Keycode::R,
+            settings: Keycode::F1,
+            fullscreen: Some(Keycode::F11),
+            zoom_in: Some(Keycode::Equals),
+            zoom_out: Some(Keycode::Minus),
+            pan_up: Some(Keycode::I),
+            pan_down: Some(Keycode::K),
+            pan_left: Some(Keycode::J),
+            pan_right: Some(Keycode::L),
+            hud: Some(Keycode::H),
+            switch: Keycode::Tab,
         };
 
         let keys = &config["key-bindings"];
+        if keys.is_null() {
+            return Ok(kb);
+        }
         if !keys.is_object() {
-            eprintln!("Invalid 'key-bindings' configuration in {}", path);
-            return kb;
-        }
-
-        let up = &keys["up"];
-        let down = &keys["down"];
-        let left = &keys["left"];
-        let right = &keys["right"];
-        let undo = &keys["undo"];
-        let quit = &keys["quit"];
-        let reset = &keys["reset-level"];
-
-        if up.is_string() {
-            match map_keys(up.as_str().unwrap()) {
-                Some (k) => kb.up = k,
-                None => eprintln!("W: unknown key binding for 'up'")
-            }
+            return Err(ConfigError::InvalidType {
+                field: "key-bindings".to_string(),
+                expected: "an object",
+                found: keys.dump()
+            });
         }
-        if down.is_string() {
-            match map_keys(down.as_str().unwrap()) {
-                Some (k) => kb.down = k,
-                None => eprintln!("W: unknown key binding for 'down'")
+        check_keys(keys, "key-bindings", &ACTIONS, warnings);
+
+        for &action in ACTIONS.iter() {
+            if let Some(key) = read_key(keys, action)? {
+                kb.set(action, key)
             }
         }
-        if left.is_string() {
-            match map_keys(left.as_str().unwrap()) {
-                Some (k) => kb.left = k,
-                None => eprintln!("W: unknown key binding for 'left'")
+        kb.check_conflicts(keys, warnings)?;
+        Ok(kb)
+    }
+
+    /* Two actions the user bound to the same key is an error. A display
+     * action loses a default the user chose for something else, other
+     * defaults shadowed that way are only worth a warning */
+    fn check_conflicts(&mut self, keys: &json::JsonValue,
+                       warnings: &mut Vec<ConfigError>) -> Result<(), ConfigError> {
+        for (i, &a) in ACTIONS.iter().enumerate() {
+            for &b in ACTIONS[i + 1..].iter() {
+                let key = match self.get(a) {
+                    Some(k) if Some(k) == self.get(b) => k,
+                    _ => continue
+                };
+                if keys[a].is_null() && keys[b].is_null() {
+                    continue
+                }
+                /* the one set by the user first */
+                let (a, b) = if keys[a].is_null() { (b, a) } else { (a, b) };
+                let conflict = ConfigError::Conflict {
+                    field: format!("key-bindings.{}", a),
+                    other: format!("key-bindings.{}", b),
+                    key: key_name(key).unwrap_or("?").to_string()
+                };
+                if keys[b].is_null() {
+                    if !self.unbind(b) {
+                        warnings.push(conflict)
+                    }
+                } else {
+                    return Err(conflict)
+                }
             }
         }
-        if right.is_string() {
-            match map_keys(right.as_str().unwrap()) {
-                Some (k) => kb.right = k,
-                None => eprintln!("W: unknown key binding for 'right'")
-            }
+        Ok(())
+    }
+
+    pub fn get(&self, action: &str) -> Option<Keycode> {
+        match action {
+            "up" => Some(self.up),
+            "down" => Some(self.down),
+            "left" => Some(self.left),
+            "right" => Some(self.right),
+            "undo" => Some(self.undo),
+            "quit" => Some(self.quit),
+            "reset-level" => Some(self.reset),
+            "settings" => Some(self.settings),
+            "fullscreen" => self.fullscreen,
+            "zoom-in" => self.zoom_in,
+            "zoom-out" => self.zoom_out,
+            "pan-up" => self.pan_up,
+            "pan-down" => self.pan_down,
+            "pan-left" => self.pan_left,
+            "pan-right" => self.pan_right,
+            "hud" => self.hud,
+            "switch-player" => Some(self.switch),
+            _ => panic!("Unknown action: {}", action)
         }
-        if undo.is_string() {
-            match map_keys(undo.as_str().unwrap()) {
-                Some (k) => kb.undo = k,
-                None => eprintln!("W: unknown key binding for 'undo'")
-            }
+    }
+
+    pub fn set(&mut self, action: &str, key: Keycode) {
+        match action {
+            "up" => self.up = key,
+            "down" => self.down = key,
+            "left" => self.left = key,
+            "right" => self.right = key,
+            "undo" => self.undo = key,
+            "quit" => self.quit = key,
+            "reset-level" => self.reset = key,
+            "settings" => self.settings = key,
+            "fullscreen" => self.fullscreen = Some(key),
+            "zoom-in" => self.zoom_in = Some(key),
+            "zoom-out" => self.zoom_out = Some(key),
+            "pan-up" => self.pan_up = Some(key),
+            "pan-down" => self.pan_down = Some(key),
+            "pan-left" => self.pan_left = Some(key),
+            "pan-right" => self.pan_right = Some(key),
+            "hud" => self.hud = Some(key),
+            "switch-player" => self.switch = key,
+            _ => panic!("Unknown action: {}", action)
         }
-        if quit.is_string() {
-            match map_keys(quit.as_str().unwrap()) {
-                Some (k) => kb.quit = k,
-                None => eprintln!("W: unknown key binding for 'quit'")
-            }
+    }
+
+    /* Leave action without a key. Returns false if it must have one */
+    fn unbind(&mut self, action: &str) -> bool {
+        match action {
+            "fullscreen" => self.fullscreen = None,
+            "zoom-in" => self.zoom_in = None,
+            "zoom-out" => self.zoom_out = None,
+            "pan-up" => self.pan_up = None,
+            "pan-down" => self.pan_down = None,
+            "pan-left" => self.pan_left = None,
+            "pan-right" => self.pan_right = None,
+            "hud" => self.hud = None,
+            _ => return false
         }
-        if reset.is_string() {
-            match map_keys(reset.as_str().unwrap()) {
-                Some (k) => kb.reset = k,
-                None => eprintln!("W: unknown key binding for 'reset-level'")
+        true
+    }
+
+    /* Unbound actions are left out */
+    fn to_json(&self, keys: &mut json::JsonValue) {
+        for &action in ACTIONS.iter() {
+            let key = match self.get(action) {
+                Some(k) => k,
+                None => continue
+            };
+            match key_name(key) {
+                Some(k) => keys[action] = k.into(),
+                None => eprintln!("W: can't save key binding for '{}'", action)
             }
         }
-        kb
+    }
+
+    /* Action currently bound to key, if any */
+    pub fn action_of(&self, key: Keycode) -> Option<&'static str> {
+        ACTIONS.iter().find(|&&a| self.get(a) == Some(key)).copied()
     }
 }
 
 pub struct Config {
     pub keys: KeyBindings,
     pub undo_level: usize,
-    pub replay_speed: u32
+    pub replay_speed: u32,
+    pub cell_size: u32, /* unscaled size of a cell, in pixels */
+    pub integer_scaling: bool,
+    pub camera_margin: u32, /* cells between the player and the window border */
+    pub scoreboard: String, /* path of the scores file */
+    pub replays: String, /* directory of the best runs */
+    pub animation_duration: u32, /* in ms, 0 to disable animations */
+    pub hud: bool, /* show the HUD when starting */
+    pub repeat_delay: u32, /* in ms before a held key repeats, 0 to disable */
+    pub repeat_rate: u32, /* repeats per second */
+    pub theme: theme::Theme,
+    pub shape_glyphs: bool, /* accessibility: shapes on top of colors */
+    pub tileset: Option<tileset::Descriptor>,
+    pub warnings: Vec<ConfigError>,
+    raw: json::JsonValue /* original document, to preserve unknown entries */
 }
 
-pub fn new(path: &str) -> Result<Config, String> {
+impl Config {
+    /* Effective configuration: existing entries keep their position,
+     * missing ones are appended */
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut config = self.raw.clone();
+        config["undo-level"] = self.undo_level.into();
+        config["replay-speed"] = self.replay_speed.into();
+        config["cell-size"] = self.cell_size.into();
+        config["integer-scaling"] = self.integer_scaling.into();
+        config["camera-margin"] = self.camera_margin.into();
+        config["scoreboard"] = self.scoreboard.as_str().into();
+        config["replays"] = self.replays.as_str().into();
+        config["animation-duration"] = self.animation_duration.into();
+        config["hud"] = self.hud.into();
+        config["repeat-delay"] = self.repeat_delay.into();
+        config["repeat-rate"] = self.repeat_rate.into();
+        config["shape-glyphs"] = self.shape_glyphs.into();
+        if !config["key-bindings"].is_object() {
+            config["key-bindings"] = json::JsonValue::new_object();
+        }
+        self.keys.to_json(&mut config["key-bindings"]);
+        config
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ConfigError> {
+        let data = format!("{}\n", self.to_json().pretty(4));
+        match fs::write(path, data) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ConfigError::Write {
+                path: path.to_string(),
+                reason: format!("{}", e)
+            })
+        }
+    }
+}
+
+pub fn new(path: &str) -> Result<Config, ConfigError> {
         let data = {
             match fs::read_to_string(path)  {
                 Ok(d) => d,
                 Err(e) =>
-                    return Err(format!("Can't read configuration file '{}': {}\n",
-                                       path, e))
+                    return Err(ConfigError::Io {
+                        path: path.to_string(),
+                        reason: format!("{}", e)
+                    })
             }
         };
+        parse(&data, path)
+}
+
+/* Configuration from the contents of the file at path */
+fn parse(data: &str, path: &str) -> Result<Config, ConfigError> {
         let config = {
-            match json::parse(&data) {
+            match json::parse(data) {
                 Ok(d) => d,
                 Err(e) =>
-                    return Err(format!("Can't parse configuration file '{}': {}\n",
-                                       path, e))
+                    return Err(ConfigError::Parse {
+                        path: path.to_string(),
+                        reason: format!("{}", e)
+                    })
             }
         };
-        let undo = &config["undo-level"];
-        if !undo.is_number() {
-            return Err(format!("Invalid 'undo-level' entry\n"));
+        if !config.is_object() {
+            return Err(ConfigError::InvalidType {
+                field: "<root>".to_string(),
+                expected: "an object",
+                found: config.dump()
+            })
         }
-        let speed = &config["replay-speed"];
-        if !speed.is_number() {
-            return Err(format!("Invalid 'replay-speed' entry\n"));
-        }
-        let kb = KeyBindings::new(&config, path);
+        let mut warnings = Vec::new();
+        check_keys(&config, "", &CONFIG_KEYS, &mut warnings);
+
+        let undo = read_uint(&config, "undo-level", 1, None)?;
+        let speed = read_uint(&config, "replay-speed", 0, None)?;
+        let cell_size = read_uint(&config, "cell-size", 1, Some(32))?;
+        let integer_scaling = read_bool(&config, "integer-scaling", false)?;
+        let margin = read_uint(&config, "camera-margin", 0, Some(3))?;
+        let animation = read_uint(&config, "animation-duration", 0, Some(120))?;
+        let repeat_delay = read_uint(&config, "repeat-delay", 0, Some(250))?;
+        let repeat_rate = read_uint(&config, "repeat-rate", 1, Some(10))?;
+        let kb = KeyBindings::new(&config, &mut warnings)?;
+        let theme = theme::from_json(&config["theme"], "theme", &mut warnings)?;
+        let glyphs = read_bool(&config, "shape-glyphs", false)?;
+        let hud = read_bool(&config, "hud", true)?;
+        let scoreboard = read_path(&config, "scoreboard")?
+            .unwrap_or("data/scores.json");
+        let replays = read_path(&config, "replays")?.unwrap_or("data/replays");
+        let tileset = match read_path(&config, "tileset")? {
+            Some(p) => Some(tileset::load_descriptor(p, &mut warnings)?),
+            None => None
+        };
         Ok(Config {
             keys: kb,
-            undo_level: undo.as_usize().unwrap(),
-            replay_speed: speed.as_u32().unwrap()
+            undo_level: undo as usize,
+            replay_speed: speed as u32,
+            cell_size: cell_size as u32,
+            integer_scaling: integer_scaling,
+            camera_margin: margin as u32,
+            scoreboard: scoreboard.to_string(),
+            replays: replays.to_string(),
+            animation_duration: animation as u32,
+            hud: hud,
+            repeat_delay: repeat_delay as u32,
+            repeat_rate: repeat_rate as u32,
+            theme: theme,
+            shape_glyphs: glyphs,
+            tileset: tileset,
+            warnings: warnings,
+            raw: config
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(text: &str) -> (Result<KeyBindings, ConfigError>, Vec<ConfigError>) {
+        let mut warnings = Vec::new();
+        let config = json::parse(&format!("{{\"key-bindings\": {}}}", text)).unwrap();
+        (KeyBindings::new(&config, &mut warnings), warnings)
+    }
+
+    fn rejected(text: &str) -> bool {
+        match bindings(text).0 {
+            Err(ConfigError::InvalidValue { .. }) => true,
+            _ => false
+        }
+    }
+
+    fn config(text: &str) -> Result<Config, ConfigError> {
+        parse(text, "test.json")
+    }
+
+    fn invalid_value(text: &str) -> bool {
+        match config(text) {
+            Err(ConfigError::InvalidValue { .. }) => true,
+            _ => false
+        }
+    }
+
+    #[test]
+    fn integers_are_checked() {
+        let ok = |text: &str| config(text).is_ok();
+        assert!(ok("{\"undo-level\": 1, \"replay-speed\": 0}"));
+        assert!(ok("{\"undo-level\": 1, \"replay-speed\": 2.0}"));
+        assert!(invalid_value("{\"undo-level\": 0, \"replay-speed\": 0}"));
+        assert!(invalid_value("{\"undo-level\": 1, \"replay-speed\": 2.5}"));
+        assert!(invalid_value("{\"undo-level\": 1, \"replay-speed\": -1}"));
+        assert!(invalid_value("{\"undo-level\": 1, \"replay-speed\": 1e10}"));
+        match config("{\"undo-level\": 1, \"replay-speed\": \"1\"}") {
+            Err(ConfigError::InvalidType { .. }) => {},
+            _ => panic!("string accepted as an integer")
+        }
+        match config("{\"undo-level\": 1}") {
+            Err(ConfigError::Missing { ref field, .. }) => assert_eq!(field, "replay-speed"),
+            _ => panic!("missing entry accepted")
+        }
+    }
+
+    #[test]
+    fn unknown_entries_are_warnings() {
+        let conf = match config("{\"undo-level\": 1, \"replay-speed\": 0, \"speed\": 2, \
+                                  \"key-bindings\": {\"jump\": \"j\"}}") {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e)
+        };
+        let fields: Vec<String> = conf.warnings.iter().filter_map(|w| match *w {
+            ConfigError::UnknownKey { ref field } => Some(field.clone()),
+            _ => None
+        }).collect();
+        assert_eq!(fields, vec!["speed", "key-bindings.jump"]);
+    }
+
+    #[test]
+    fn to_json_round_trip() {
+        let text = "{\"zzz\": [1, 2], \"replay-speed\": 3, \"undo-level\": 5, \
+                    \"key-bindings\": {\"up\": \"w\", \"extra\": \"x\"}}";
+        let out = match config(text) {
+            Ok(c) => c.to_json(),
+            Err(e) => panic!("{}", e)
+        };
+        /* unknown entries and the original order are kept */
+        let order: Vec<&str> = out.entries().map(|(k, _)| k).take(4).collect();
+        assert_eq!(order, vec!["zzz", "replay-speed", "undo-level", "key-bindings"]);
+        assert_eq!(out["zzz"].dump(), "[1,2]");
+        assert_eq!(out["undo-level"].as_u32(), Some(5));
+        assert_eq!(out["cell-size"].as_u32(), Some(32));
+        assert_eq!(out["key-bindings"]["up"].as_str(), Some("w"));
+        assert_eq!(out["key-bindings"]["extra"].as_str(), Some("x"));
+        assert_eq!(out["key-bindings"]["quit"].as_str(), Some("q"));
+        /* and writing it again changes nothing */
+        match config(&out.dump()) {
+            Ok(c) => assert_eq!(c.to_json().dump(), out.dump()),
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(map_keys("a"), Some(Keycode::A));
+        assert_eq!(map_keys("Z"), Some(Keycode::Z));
+        assert_eq!(map_keys("arrow-up"), Some(Keycode::Up));
+        assert_eq!(map_keys("f12"), Some(Keycode::F12));
+        assert_eq!(map_keys(""), None);
+        assert_eq!(map_keys("1"), None);
+        for &(name, key) in NAMED_KEYS.iter() {
+            assert_eq!(key_name(key), Some(name))
+        }
+    }
+
+    #[test]
+    fn only_single_letters_fall_back() {
+        for name in ["enter", "wasd", "f13", "shift"].iter() {
+            assert!(rejected(&format!("{{\"undo\": \"{}\"}}", name)), "{}", name)
+        }
+        assert!(!rejected("{\"undo\": \"u\"}"));
+    }
+
+    #[test]
+    fn escape_cannot_be_bound() {
+        match bindings("{\"quit\": \"escape\"}").0 {
+            Err(ConfigError::InvalidValue { expected, .. }) =>
+                assert_eq!(expected, "a key other than escape"),
+            _ => panic!("escape accepted")
+        }
+    }
+
+    #[test]
+    fn same_key_twice_is_an_error() {
+        match bindings("{\"up\": \"w\", \"undo\": \"w\"}").0 {
+            Err(ConfigError::Conflict { ref field, ref other, ref key }) => {
+                assert_eq!(field, "key-bindings.up");
+                assert_eq!(other, "key-bindings.undo");
+                assert_eq!(key, "w")
+            },
+            _ => panic!("conflict not detected")
+        }
+    }
+
+    #[test]
+    fn user_keys_win_over_view_defaults() {
+        let (kb, warnings) = bindings("{\"up\": \"k\", \"down\": \"j\", \"left\": \"h\", \
+                                        \"right\": \"l\", \"zoom-in\": \"i\"}");
+        assert!(warnings.is_empty());
+        let kb = match kb {
+            Ok(kb) => kb,
+            Err(e) => panic!("{}", e)
+        };
+        assert_eq!(kb.action_of(Keycode::K), Some("up"));
+        assert_eq!(kb.action_of(Keycode::J), Some("down"));
+        assert_eq!(kb.action_of(Keycode::H), Some("left"));
+        assert_eq!(kb.action_of(Keycode::L), Some("right"));
+        assert_eq!(kb.action_of(Keycode::I), Some("zoom-in"));
+        assert_eq!(kb.pan_up, None);
+        assert_eq!(kb.pan_down, None);
+        assert_eq!(kb.pan_left, None);
+        assert_eq!(kb.pan_right, None);
+        assert_eq!(kb.hud, None);
+        assert_eq!(kb.zoom_out, Some(Keycode::Minus));
+    }
+
+    #[test]
+    fn shadowed_default_is_a_warning() {
+        let (kb, warnings) = bindings("{\"up\": \"q\"}");
+        assert!(kb.is_ok());
+        match warnings.as_slice() {
+            [ConfigError::Conflict { ref other, .. }] =>
+                assert_eq!(other, "key-bindings.quit"),
+            _ => panic!("expected one conflict warning")
+        }
+    }
+}
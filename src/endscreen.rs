@@ -0,0 +1,106 @@
+extern crate sdl2;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use hud;
+use play::Outcome;
+use score::Score;
+use text;
+use ui::Ui;
+
+#[derive(Copy, Clone)]
+pub enum Choice {
+    Retry,
+    Next,
+    Replay,
+    Quit
+}
+
+/* What is known about the scoreboard when the level ends */
+pub enum Ranking {
+    Ranked(usize),
+    Unranked,
+    Best(Option<Score>) /* the level was not won, or was a replay */
+}
+
+/* Win or lose screen, with the statistics of the level and what to do
+ * next */
+pub fn run(ui: &mut Ui, outcome: &Outcome, level: &str, ranking: Ranking,
+           has_next: bool) -> Choice {
+    let mut choices = vec![("Retry", Choice::Retry)];
+    if has_next {
+        choices.push(("Next level", Choice::Next));
+    }
+    choices.push(("Watch replay", Choice::Replay));
+    choices.push(("Quit", Choice::Quit));
+    let n = choices.len();
+    /* after a win, moving on is the most likely choice */
+    let mut selected = if outcome.won && has_next { 1 } else { 0 };
+
+    let stats = format!("Moves: {}  Pushes: {}  Time: {}",
+                        outcome.stats.moves, outcome.stats.pushes,
+                        hud::format_time(outcome.elapsed));
+    let rank = match ranking {
+        Ranking::Ranked(r) => format!("Rank #{} on the scoreboard", r),
+        Ranking::Unranked => "Not in the top scores".to_string(),
+        Ranking::Best(Some(s)) => format!("Best: {} moves, {} pushes in {}",
+                                          s.moves, s.pushes,
+                                          hud::format_time(s.time)),
+        Ranking::Best(None) => "Not solved yet".to_string()
+    };
+
+    loop {
+        for event in ui.events.poll_iter() {
+            match event {
+                Event::Quit {..} => return Choice::Quit,
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    match key {
+                        Keycode::Up => selected = (selected + n - 1) % n,
+                        Keycode::Down => selected = (selected + 1) % n,
+                        Keycode::Return => return choices[selected].1,
+                        Keycode::Escape => return Choice::Quit,
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let canvas = &mut ui.canvas;
+        let font = &ui.font;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        let white = Color::RGB(255, 255, 255);
+        let line = font.recommended_line_spacing();
+        let (w, h) = canvas.output_size().unwrap();
+        let mut y = line;
+        if outcome.won {
+            let size = (h / 3).min(w / 2);
+            let r = Rect::new((w - size) as i32 / 2, y, size, size);
+            canvas.copy(&ui.win, None, Some(r)).unwrap();
+            y += size as i32 + line;
+        }
+        let title = if outcome.won { "Level complete!" } else { "Level failed" };
+        text::draw_centered(canvas, font, title, white, y);
+        y += line;
+        text::draw_centered(canvas, font, level, white, y);
+        y += 2 * line;
+        text::draw_centered(canvas, font, &stats, white, y);
+        y += line;
+        text::draw_centered(canvas, font, &rank, Color::RGB(255, 255, 51), y);
+        y += 2 * line;
+        for (i, &(label, _)) in choices.iter().enumerate() {
+            if i == selected {
+                canvas.set_draw_color(Color::RGB(96, 96, 96));
+                canvas.fill_rect(Rect::new(0, y, w, line as u32)).unwrap();
+            }
+            text::draw_centered(canvas, font, label, white, y);
+            y += line;
+        }
+        canvas.present();
+    }
+}
@@ -2,10 +2,19 @@ extern crate sdl2;
 
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 
+use std::cmp;
+use std::mem;
+use std::collections::{HashMap, VecDeque};
 use std::io::BufReader;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
+
+use glyph::{self, Glyph};
+use theme::{self, Theme};
+use tileset::Tileset;
 
 fn read_int(line: &String) -> Result<i32, String> {
     match line.parse::<i32>() {
@@ -16,6 +25,7 @@ fn read_int(line: &String) -> Result<i32, String> {
 }
 
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Direction {
     Up,
     Down,
@@ -23,6 +33,20 @@ pub enum Direction {
     Right
 }
 
+const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down,
+                                    Direction::Left, Direction::Right];
+
+impl Direction {
+    pub fn delta(&self) -> (i32, i32) {
+        match *self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0)
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 enum CellType {
     Wall,
@@ -30,40 +54,184 @@ enum CellType {
     Empty,
     Block,
     Exit,
+    Hole, /* swallows a pushed block, and becomes floor */
 }
 
 #[derive(Copy, Clone)]
 struct Cell {
     kind: CellType,
-    goal: bool
+    goal: bool,
+    goal_color: Option<usize>, /* only blocks of its color count on it */
+    block_color: Option<usize>, /* color of the block standing there */
+    ice: bool, /* things entering it slide */
+    plate: Option<usize>, /* ID of the gates it opens */
+    gate: Option<usize>, /* ID of the plates opening it */
+    key: Option<usize>, /* color of a key lying there */
+    door: Option<usize>, /* color of the key opening this locked door */
+    teleport: Option<Pad>,
+    arrow: Option<(Direction, bool)> /* the only way across, and whether
+                                      * blocks must follow it too */
+}
+
+/* Teleporter: where its partner is, and whether it sends pushed blocks
+ * there too or only the player */
+#[derive(Copy, Clone)]
+struct Pad {
+    to: (i32, i32),
+    blocks: bool
+}
+
+/* Map cells declared in the legend, after the grid */
+#[derive(Copy, Clone)]
+enum Special {
+    Plate(usize),
+    Gate(usize),
+    Key(usize), /* palette colors */
+    Door(usize),
+    Teleport(usize, bool), /* sends blocks too */
+    Arrow(Direction, bool), /* blocks follow it too */
+    Block(usize),
+    Goal(usize)
 }
 
+/* Cells with a meaning of their own, that the legend can't redefine */
+const GLYPHS: &str = "s. gbcxiIo^v<>";
+
 impl Cell {
-    fn color(&self, goals_left: i32) -> Color {
+    fn color(&self, state: &State, theme: &Theme) -> Color {
+        if let Some(c) = self.door {
+            return theme::palette(c)
+        }
+        match self.kind {
+            CellType::Wall => theme.wall,
+            CellType::Hole => theme.hole,
+            CellType::Block => {
+                let base = if self.counts() { theme.block_on_goal } else { theme.block };
+                self.block_color.map_or(base, |c| theme::tint(base, c))
+            },
+            CellType::Crate => theme.crate_,
+            CellType::Exit => if state.remaining() > 0 {
+                theme.exit_closed
+            } else {
+                theme.exit_open
+            },
+            CellType::Empty => if self.is_closed(&state.gates) {
+                theme.gate_closed
+            } else if self.goal {
+                self.goal_color.map_or(theme.goal, |c| theme::tint(theme.goal, c))
+            } else if self.plate.is_some() {
+                theme.plate
+            } else if self.gate.is_some() {
+                theme.gate_open
+            } else if self.teleport.is_some() {
+                theme.teleport
+            } else if self.arrow.is_some() {
+                theme.arrow
+            } else if self.ice {
+                theme.ice
+            } else {
+                theme.floor
+            }
+        }
+    }
+
+    /* Doors, and keys lying on the floor, are drawn apart, as they are
+     * shown in any skin */
+    fn glyph(&self, state: &State) -> Option<Glyph> {
+        if self.door.is_some() {
+            return None
+        }
         match self.kind {
-            CellType::Wall => Color::RGB(96, 96, 96),
-            CellType::Block => if self.goal {
-                Color::RGB(103, 240, 139)
+            CellType::Wall => None,
+            CellType::Hole => Some(Glyph::Pit),
+            CellType::Block => if self.counts() {
+                Some(Glyph::Ring)
             } else {
-                Color::RGB(102, 51, 0)
+                Some(Glyph::Square)
             },
-            CellType::Crate => Color::RGB(255, 128, 0),
-            CellType::Exit => if goals_left > 0 {
-                Color::RGB(0, 0, 0)
+            CellType::Crate => Some(Glyph::Stripes),
+            CellType::Exit => if state.remaining() > 0 {
+                Some(Glyph::Bars)
             } else {
-                Color::RGB(255, 255, 255)
+                Some(Glyph::Arrow)
             },
-            CellType::Empty => if self.goal {
-                Color::RGB(255, 255, 51)
+            CellType::Empty if self.key.is_some() => None,
+            CellType::Empty => if self.is_closed(&state.gates) {
+                Some(Glyph::Grid)
+            } else if self.goal {
+                Some(Glyph::Cross)
+            } else if self.plate.is_some() {
+                Some(Glyph::Dot)
+            } else if self.teleport.is_some() {
+                Some(Glyph::Portal)
+            } else if let Some((dir, _)) = self.arrow {
+                Some(Glyph::OneWay(dir))
+            } else if self.ice {
+                Some(Glyph::Hatch)
             } else {
-                Color::RGB(192, 192, 192)
+                None
             }
         }
     }
 
-    fn is_free(&self, solved: bool) -> bool {
+    /* Tiles to draw, bottom to top */
+    fn tiles(&self, state: &State) -> Vec<&'static str> {
+        let mut tiles = Vec::new();
+        match self.kind {
+            CellType::Wall => tiles.push("wall"),
+            CellType::Hole => tiles.push("hole"),
+            CellType::Exit => tiles.push(if state.remaining() > 0 {
+                "exit-closed"
+            } else {
+                "exit-open"
+            }),
+            _ => tiles.push(if self.is_closed(&state.gates) {
+                "gate-closed"
+            } else if self.gate.is_some() {
+                "gate-open"
+            } else if self.ice {
+                "ice"
+            } else {
+                "floor"
+            })
+        }
+        if self.goal {
+            tiles.push("goal")
+        }
+        if self.plate.is_some() {
+            tiles.push("plate")
+        }
+        if self.teleport.is_some() {
+            tiles.push("teleport")
+        }
+        if let Some((dir, _)) = self.arrow {
+            tiles.push(match dir {
+                Direction::Up => "arrow-up",
+                Direction::Down => "arrow-down",
+                Direction::Left => "arrow-left",
+                Direction::Right => "arrow-right"
+            })
+        }
+        match self.kind {
+            CellType::Block => tiles.push(if self.counts() {
+                "block-on-goal"
+            } else {
+                "block"
+            }),
+            CellType::Crate => tiles.push("crate"),
+            _ => {}
+        }
+        tiles
+    }
+
+    /* gates tells which gate IDs are open */
+    fn is_free(&self, solved: bool, gates: &[bool]) -> bool {
+        if self.is_closed(gates) || self.door.is_some() {
+            return false
+        }
         match self.kind {
             CellType::Wall => false,
+            CellType::Hole => false,
             CellType::Exit => solved,
             CellType::Block => false,
             CellType::Empty => true,
@@ -71,6 +239,13 @@ impl Cell {
         }
     }
 
+    fn is_closed(&self, gates: &[bool]) -> bool {
+        match self.gate {
+            Some(g) => !gates[g],
+            None => false
+        }
+    }
+
     fn is_movable(&self) -> bool {
         match self.kind {
             CellType::Block => true,
@@ -79,15 +254,28 @@ impl Cell {
         }
     }
 
-    fn is_crate(&self) -> bool {
+    fn is_hole(&self) -> bool {
         match self.kind {
-            CellType::Crate => true,
+            CellType::Hole => true,
+            _ => false
+        }
+    }
+
+    /* A block on a goal of its color */
+    fn counts(&self) -> bool {
+        match self.kind {
+            CellType::Block => self.goal && self.block_color == self.goal_color,
             _ => false
         }
     }
 
-    fn is_goal(&self) -> bool {
-        self.goal
+    /* Color of what is drawn there, for blocks and goals */
+    fn tint(&self) -> Option<usize> {
+        match self.kind {
+            CellType::Block => self.block_color,
+            _ if self.goal => self.goal_color,
+            _ => None
+        }
     }
 
     fn is_exit(&self) -> bool {
@@ -98,69 +286,414 @@ impl Cell {
     }
 
     fn non_goal(kind: CellType) -> Cell {
-        Cell { kind: kind, goal: false }
+        Cell {
+            kind: kind, goal: false, goal_color: None, block_color: None, ice: false,
+            plate: None, gate: None, key: None, door: None, teleport: None, arrow: None
+        }
     }
 }
 
 #[derive(Clone)]
 struct Player {
     x: i32,
-    y: i32
+    y: i32,
+    facing: Direction
+}
+
+impl Player {
+    fn tile(&self) -> &'static str {
+        match self.facing {
+            Direction::Up => "player-up",
+            Direction::Down => "player-down",
+            Direction::Left => "player-left",
+            Direction::Right => "player-right"
+        }
+    }
+}
+
+/* Where the board is drawn: top left corner of the board and size of a
+ * cell, in pixels */
+#[derive(Copy, Clone)]
+pub struct View {
+    pub x: i32,
+    pub y: i32,
+    pub cell: u32
+}
+
+/* Everything that changes how a map looks, but not how it plays */
+pub struct Skin<'a, 'r: 'a> {
+    pub theme: &'a Theme,
+    pub glyphs: bool, /* accessibility: shapes on top of colors */
+    pub tileset: Option<&'a Tileset<'r>>
+}
+
+/* Progress on the current level, as shown to the player */
+#[derive(Copy, Clone)]
+pub struct Stats {
+    pub moves: u32,
+    pub pushes: u32,
+    pub goals_left: i32,
+    pub undo_depth: usize
+}
+
+/* Index of a block or goal color in counts per color, plain ones first */
+fn class(color: Option<usize>) -> usize {
+    color.map_or(0, |c| c + 1)
 }
 
 #[derive(Clone)]
 struct State {
     data: Vec<Vec<Cell>>,
-    player: Player,
+    player: Player, /* the one moving */
+    others: Vec<Player>, /* waiting for their turn, next one first */
     solved: bool,
-    goals_left: i32
+    goals_left: Vec<i32>, /* per color, plain ones first */
+    gates: Vec<bool>, /* which gate IDs are open */
+    keys: Vec<u32>, /* keys held, per color */
+    moves: u32,
+    pushes: u32
 }
 
 impl State {
     fn inspect(&mut self, x1: usize, y1: usize, x2: usize, y2: usize,
             next: bool) -> bool {
+        let (from, to) = ((self.player.x, self.player.y), (x1 as i32, y1 as i32));
+        if !self.passes(from, to, false) || self.occupied(to.0, to.1) {
+            return false
+        }
+        if let Some(c) = self.data[y1][x1].door {
+            /* walking into a locked door opens it with a key */
+            if self.keys[c] > 0 {
+                self.keys[c] = self.keys[c] - 1;
+                self.data[y1][x1].door = None
+            }
+        }
         let cell = self.data[y1][x1];
-        if cell.is_free(self.solved) {
+        if cell.is_free(self.solved, &self.gates) {
             return true
         }
-        if !next { return false }
+        if !next || !self.passes(to, (x2 as i32, y2 as i32), true) ||
+            self.occupied(x2 as i32, y2 as i32) { return false }
         let ncell = self.data[y2][x2];
-        if cell.is_movable() && ncell.is_free(self.solved) {
-            if cell.is_crate() {
-                /* Move but can't succeed */
-                self.data[y2][x2].kind = CellType::Crate;
-                self.data[y1][x1].kind = CellType::Empty;
-            } else {
-                if self.data[y2][x2].is_goal() {
-                    /* Block on goal -> success */
-                    self.goals_left = self.goals_left - 1
-                }
-                self.data[y2][x2].kind = CellType::Block;
-
-                if self.data[y1][x1].is_goal() {
-                    /* Block removed from goal -> failure */
-                    self.goals_left = self.goals_left + 1
-                }
-                self.data[y1][x1].kind = CellType::Empty
-            }
+        if cell.is_movable() && ncell.is_free(self.solved, &self.gates) {
+            self.move_block(x1, y1, x2, y2);
+            self.pushes = self.pushes + 1;
             /* finally, let's move */
             return true
         }
+        if cell.is_movable() && ncell.is_hole() {
+            self.fill_hole(x1, y1, x2, y2);
+            self.pushes = self.pushes + 1;
+            return true
+        }
         return false;
     }
+
+    /* The block at (x1, y1) falls into the hole at (x2, y2), which becomes
+     * floor */
+    fn fill_hole(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        let block = self.data[y1][x1];
+        if block.counts() {
+            let k = class(block.block_color);
+            self.goals_left[k] = self.goals_left[k] + 1
+        }
+        self.data[y1][x1].kind = CellType::Empty;
+        self.data[y1][x1].block_color = None;
+        self.data[y2][x2].kind = CellType::Empty
+    }
+
+    /* Hole a block sliding on ice at (x, y) in dir falls into, if any */
+    fn falls(&self, x: i32, y: i32, dir: Direction) -> Option<(i32, i32)> {
+        let (dx, dy) = dir.delta();
+        let (nx, ny) = (x + dx, y + dy);
+        let (h, w) = (self.data.len() as i32, self.data[0].len() as i32);
+        if self.data[y as usize][x as usize].ice && nx >= 0 && ny >= 0 && nx < w &&
+            ny < h && self.data[ny as usize][nx as usize].is_hole() {
+            Some((nx, ny))
+        } else {
+            None
+        }
+    }
+
+    /* Crates move but never count, blocks only count on goals of their
+     * color */
+    fn move_block(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        let block = self.data[y1][x1];
+        let k = class(block.block_color);
+        if block.counts() {
+            /* Block removed from goal -> failure */
+            self.goals_left[k] = self.goals_left[k] + 1
+        }
+        self.data[y1][x1].kind = CellType::Empty;
+        self.data[y1][x1].block_color = None;
+
+        self.data[y2][x2].kind = block.kind;
+        self.data[y2][x2].block_color = block.block_color;
+        if self.data[y2][x2].counts() {
+            /* Block on goal -> success */
+            self.goals_left[k] = self.goals_left[k] - 1
+        }
+    }
+
+    /* Goals left, all colors together */
+    fn remaining(&self) -> i32 {
+        self.goals_left.iter().sum()
+    }
+
+    fn is_free(&self, x: i32, y: i32) -> bool {
+        self.data[y as usize][x as usize].is_free(self.solved, &self.gates) &&
+            !self.occupied(x, y)
+    }
+
+    /* Is one of the waiting players at (x, y) */
+    fn occupied(&self, x: i32, y: i32) -> bool {
+        self.others.iter().any(|p| (p.x, p.y) == (x, y))
+    }
+
+    fn pick_key(&mut self) {
+        let (x, y) = (self.player.x as usize, self.player.y as usize);
+        if let Some(c) = self.data[y][x].key {
+            self.keys[c] = self.keys[c] + 1;
+            self.data[y][x].key = None
+        }
+    }
+
+    /* Which gates are open: all their plates are pressed, or something
+     * stands in the gate so that it can't close. The moving player only
+     * counts with_player, and nothing at ignore counts */
+    fn open_gates(&self, with_player: bool, ignore: Option<(i32, i32)>) -> Vec<bool> {
+        let n = self.gates.len();
+        let mut pressed = vec![true; n];
+        let mut blocked = vec![false; n];
+        for (j, row) in self.data.iter().enumerate() {
+            for (i, cell) in row.iter().enumerate() {
+                let here = (i as i32, j as i32);
+                let player = (with_player && here == (self.player.x, self.player.y)) ||
+                    self.occupied(here.0, here.1);
+                let occupied = Some(here) != ignore && (cell.is_movable() || player);
+                if let Some(p) = cell.plate {
+                    pressed[p] = pressed[p] && occupied
+                }
+                if let Some(g) = cell.gate {
+                    blocked[g] = blocked[g] || occupied
+                }
+            }
+        }
+        (0..n).map(|k| pressed[k] || blocked[k]).collect()
+    }
+
+    /* Can something go from from to the next cell to, as far as arrows
+     * are concerned: they are only entered going their way, and never left
+     * going back. Only some arrows apply to blocks */
+    fn passes(&self, from: (i32, i32), to: (i32, i32), block: bool) -> bool {
+        let way = (to.0 - from.0, to.1 - from.1);
+        let arrow = |(x, y): (i32, i32)| match self.data[y as usize][x as usize].arrow {
+            Some((dir, blocks)) if blocks || !block => Some(dir.delta()),
+            _ => None
+        };
+        let back = (-way.0, -way.1);
+        arrow(to).iter().all(|&a| a == way) && arrow(from).iter().all(|&a| a != back)
+    }
+
+    /* Where something that just entered (x, y) going in dir stops: it
+     * keeps sliding while on ice, until the next cell isn't free or its
+     * arrow is the wrong way */
+    fn slide<F>(&self, x: i32, y: i32, dir: Direction, block: bool, free: F) -> (i32, i32)
+        where F: Fn(i32, i32) -> bool {
+        let (dx, dy) = dir.delta();
+        let (h, w) = (self.data.len() as i32, self.data[0].len() as i32);
+        let (mut x, mut y) = (x, y);
+        while self.data[y as usize][x as usize].ice {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= w || ny >= h || !free(nx, ny) ||
+                !self.passes((x, y), (nx, ny), block) {
+                break
+            }
+            x = nx;
+            y = ny
+        }
+        (x, y)
+    }
+
+    /* Where something that stopped on (x, y) ends up: on the partner of a
+     * teleporter, if free. Blocks only go through the pads sending them */
+    fn teleport<F>(&self, x: i32, y: i32, block: bool, free: F) -> (i32, i32)
+        where F: Fn(i32, i32) -> bool {
+        match self.data[y as usize][x as usize].teleport {
+            Some(pad) if (pad.blocks || !block) && free(pad.to.0, pad.to.1) => pad.to,
+            _ => (x, y)
+        }
+    }
+}
+
+/* Draw a cell at r. Moving objects are drawn alone, without the floor
+ * under them */
+fn draw_cell(canvas: &mut sdl2::render::WindowCanvas, skin: &Skin, cell: &Cell,
+             state: &State, r: Rect, alone: bool) {
+    let color = cell.color(state, skin.theme);
+    let mut tiles = cell.tiles(state);
+    if alone {
+        tiles = tiles.split_off(tiles.len() - 1)
+    }
+    let tiled = match skin.tileset {
+        /* there are no door tiles, doors are drawn in their color */
+        Some(ts) if cell.door.is_none() && tiles.iter().all(|t| ts.has(t)) => {
+            for t in tiles {
+                ts.draw(canvas, t, r);
+            }
+            true
+        },
+        _ => {
+            canvas.set_draw_color(color);
+            canvas.fill_rect(r).unwrap();
+            false
+        }
+    };
+    if let (true, Some(c)) = (tiled, cell.tint()) {
+        /* tiles aren't tinted, colored blocks and goals get a frame */
+        canvas.set_draw_color(theme::palette(c));
+        for k in 0..2 {
+            let s = r.width() as i32 - 2 * k;
+            canvas.draw_rect(Rect::new(r.x() + k, r.y() + k, s as u32, s as u32)).unwrap();
+        }
+    }
+    if let Some(c) = cell.key {
+        glyph::draw(canvas, Glyph::Key, r, theme::palette(c))
+    }
+    if cell.door.is_some() {
+        glyph::draw(canvas, Glyph::Lock, r, glyph::contrast(color))
+    }
+    if skin.glyphs {
+        if let Some(g) = cell.glyph(state) {
+            glyph::draw(canvas, g, r, glyph::contrast(color))
+        }
+    }
+}
+
+fn draw_player(canvas: &mut sdl2::render::WindowCanvas, skin: &Skin, player: &Player,
+               r: Rect, waiting: bool) {
+    let drawn = match skin.tileset {
+        Some(ts) => ts.draw(canvas, player.tile(), r),
+        None => false
+    };
+    if !drawn {
+        canvas.set_draw_color(skin.theme.player);
+        canvas.fill_rect(r).unwrap();
+    }
+    if skin.glyphs {
+        glyph::draw(canvas, Glyph::Diamond, r, glyph::contrast(skin.theme.player))
+    }
+    if waiting {
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 112));
+        canvas.fill_rect(r).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+/* How a walk first reached a cell: how many moves it took, and the last
+ * one */
+#[derive(Copy, Clone)]
+struct Step {
+    dist: u32,
+    from: (i32, i32),
+    dir: Direction
+}
+
+/* Last successful move, kept to animate it: where the player went from and
+ * to, and the same for the pushed block if any, with the block as it was
+ * since it may have fallen into a hole */
+#[derive(Copy, Clone)]
+struct Motion {
+    from: (i32, i32),
+    to: (i32, i32),
+    block: Option<BlockMotion>
 }
 
+type BlockMotion = ((i32, i32), (i32, i32), Cell);
+
 pub struct Map {
+    pub name: String,
     pub width: i32,
     pub height: i32,
     lines: Vec<String>, /* currently usued, will be used for reset */
     max_undo: usize,
     cell_size: u32,
-    states: Vec<State>
+    all_exit: bool, /* every player must leave, not just one */
+    states: Vec<State>,
+    last_move: Option<Motion>
+}
+
+/* Meaning of each character, how many IDs, and whether all players must
+ * exit */
+type Legend = (HashMap<char, Special>, usize, bool);
+
+/* Legend lines: "<char> plate <id>", "<char> gate <id>" or
+ * "<char> teleport <id> [blocks]", the ID being letters or digits, numbered
+ * in order of appearance. "<char> key <color>", "<char> door <color>",
+ * "<char> block <color>" or "<char> goal <color>", with a color from the
+ * palette. "<char> arrow <direction> [blocks]". "exit any" or "exit all"
+ * tells whether one player reaching the exit is enough, or all of them */
+fn read_legend(lines: &[String]) -> Result<Legend, String> {
+    let mut legend = HashMap::new();
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut all_exit = false;
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue
+        }
+        if words.len() == 2 && words[0] == "exit" {
+            all_exit = match words[1] {
+                "any" => false,
+                "all" => true,
+                _ => return Err(format!("Invalid legend entry: {}", line))
+            };
+            continue
+        }
+        let c = match words[0].chars().next() {
+            Some(c) if words[0].chars().count() == 1 && !GLYPHS.contains(c) => c,
+            _ => return Err(format!("Invalid legend entry: {}", line))
+        };
+        let blocks = words.len() == 4 && (words[1] == "teleport" || words[1] == "arrow") &&
+            words[3] == "blocks";
+        if (words.len() != 3 && !blocks) ||
+            !words[2].chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid legend entry: {}", line))
+        }
+        let next = ids.len();
+        let special = match words[1] {
+            "plate" => Special::Plate(*ids.entry(words[2].to_string()).or_insert(next)),
+            "gate" => Special::Gate(*ids.entry(words[2].to_string()).or_insert(next)),
+            "teleport" => Special::Teleport(*ids.entry(words[2].to_string()).or_insert(next),
+                                            blocks),
+            "arrow" => match words[2] {
+                "up" => Special::Arrow(Direction::Up, blocks),
+                "down" => Special::Arrow(Direction::Down, blocks),
+                "left" => Special::Arrow(Direction::Left, blocks),
+                "right" => Special::Arrow(Direction::Right, blocks),
+                _ => return Err(format!("Unknown direction: {}", words[2]))
+            },
+            "key" | "door" | "block" | "goal" => match theme::palette_index(words[2]) {
+                Some(c) => match words[1] {
+                    "key" => Special::Key(c),
+                    "door" => Special::Door(c),
+                    "block" => Special::Block(c),
+                    _ => Special::Goal(c)
+                },
+                None => return Err(format!("Unknown color: {}", words[2]))
+            },
+            _ => return Err(format!("Invalid legend entry: {}", line))
+        };
+        if legend.insert(c, special).is_some() {
+            return Err(format!("Legend defines '{}' twice", c))
+        }
+    }
+    Ok((legend, ids.len(), all_exit))
 }
 
 impl Map {
-    fn load(lines: &Vec<String>) -> Result<(i32, i32, State), String> {
+    fn load(lines: &Vec<String>) -> Result<(i32, i32, State, bool), String> {
         let mut iter = lines.into_iter();
         let width = match iter.next() {
             Some(l) => read_int(l)?,
@@ -170,13 +703,19 @@ impl Map {
             Some(l) => read_int(l)?,
             None => return Err(format!("Invalid map format\n"))
         };
+        let (legend, num_ids, all_exit) = read_legend(&lines[cmp::min(lines.len(),
+                                                            2 + height as usize)..])?;
+        let mut plates = vec![false; num_ids];
+        let mut gates = vec![false; num_ids];
+        let mut keys = vec![false; theme::PALETTE.len()];
+        let mut doors = vec![false; theme::PALETTE.len()];
+        let mut pads = vec![Vec::new(); num_ids];
         let mut map = Vec::new();
-        let mut x = 0;
-        let mut y = 0;
-        let mut start = false;
+        let mut starts = Vec::new();
         let mut exit_cell = false;
-        let mut num_blocks = 0;
-        let mut num_goals = 0;
+        let mut blocks = vec![0; theme::PALETTE.len() + 1];
+        let mut goals = vec![0; theme::PALETTE.len() + 1];
+        let mut num_holes = 0;
 
         for j in 0..height {
             let line: Vec<char> = match iter.next() {
@@ -189,26 +728,38 @@ impl Map {
                 row.push(
                     match c {
                         's' => {
-                            if !start {
-                                start = !start;
-                                x = i;
-                                y= j;
-                                Cell::non_goal(CellType::Empty)
-                            } else {
-                                return Err(format!("Multiple start points"))
-                            }
+                            starts.push(Player { x: i, y: j, facing: Direction::Down });
+                            Cell::non_goal(CellType::Empty)
                         },
                         '.' => Cell::non_goal(CellType::Wall),
                         ' ' => Cell::non_goal(CellType::Empty),
                         'g' => {
-                            num_goals = num_goals + 1;
-                            Cell { kind: CellType::Empty, goal: true }
+                            goals[0] = goals[0] + 1;
+                            Cell { goal: true, ..Cell::non_goal(CellType::Empty) }
+                        },
+                        'i' => Cell { ice: true, ..Cell::non_goal(CellType::Empty) },
+                        '^' | 'v' | '<' | '>' => {
+                            let dir = match c {
+                                '^' => Direction::Up,
+                                'v' => Direction::Down,
+                                '<' => Direction::Left,
+                                _ => Direction::Right
+                            };
+                            Cell { arrow: Some((dir, false)), ..Cell::non_goal(CellType::Empty) }
+                        },
+                        'I' => {
+                            goals[0] = goals[0] + 1;
+                            Cell { goal: true, ice: true, ..Cell::non_goal(CellType::Empty) }
                         },
                         'b' => {
-                            num_blocks = num_blocks + 1;
+                            blocks[0] = blocks[0] + 1;
                             Cell::non_goal(CellType::Block)
                         },
                         'c' => Cell::non_goal(CellType::Crate),
+                        'o' => {
+                            num_holes = num_holes + 1;
+                            Cell::non_goal(CellType::Hole)
+                        },
                         'x' => {
                             if !exit_cell {
                                 exit_cell = !exit_cell;
@@ -217,32 +768,94 @@ impl Map {
                                 return Err(format!("Multiple exit points"))
                             }
                         },
-                        _  => return Err(format!("Invalid map: {}", c))
+                        _ => match legend.get(&c) {
+                            Some(&Special::Plate(id)) => {
+                                plates[id] = true;
+                                Cell { plate: Some(id), ..Cell::non_goal(CellType::Empty) }
+                            },
+                            Some(&Special::Gate(id)) => {
+                                gates[id] = true;
+                                Cell { gate: Some(id), ..Cell::non_goal(CellType::Empty) }
+                            },
+                            Some(&Special::Key(c)) => {
+                                keys[c] = true;
+                                Cell { key: Some(c), ..Cell::non_goal(CellType::Empty) }
+                            },
+                            Some(&Special::Door(c)) => {
+                                doors[c] = true;
+                                Cell { door: Some(c), ..Cell::non_goal(CellType::Empty) }
+                            },
+                            Some(&Special::Teleport(id, blocks)) => {
+                                /* linked to its partner once the map is read */
+                                pads[id].push((i, j));
+                                let pad = Pad { to: (i, j), blocks: blocks };
+                                Cell { teleport: Some(pad), ..Cell::non_goal(CellType::Empty) }
+                            },
+                            Some(&Special::Block(c)) => {
+                                blocks[class(Some(c))] = blocks[class(Some(c))] + 1;
+                                Cell { block_color: Some(c), ..Cell::non_goal(CellType::Block) }
+                            },
+                            Some(&Special::Goal(c)) => {
+                                goals[class(Some(c))] = goals[class(Some(c))] + 1;
+                                Cell { goal: true, goal_color: Some(c),
+                                       ..Cell::non_goal(CellType::Empty) }
+                            },
+                            Some(&Special::Arrow(dir, blocks)) => {
+                                let arrow = Some((dir, blocks));
+                                Cell { arrow: arrow, ..Cell::non_goal(CellType::Empty) }
+                            },
+                            None => return Err(format!("Invalid map: {}", c))
+                        }
                     }
                 )
             }
             map.push(row);
         }
-        if !start {
+        let num_blocks: i32 = blocks.iter().sum();
+        let num_goals: i32 = goals.iter().sum();
+        if starts.is_empty() {
             return Err(format!("Missing start point"))
         } else if !exit_cell {
             return Err(format!("Missing exit point"))
         } else if num_goals <= 0 {
             return Err(format!("Not enough goals"))
-        } else if num_goals != num_blocks {
+        } else if (0..goals.len()).any(|k| blocks[k] < goals[k]) ||
+            num_blocks > num_goals + num_holes {
+            /* enough blocks of each color, and each hole can take one spare
+             * block */
             return Err(format!("Block/Goal mismatch"))
+        } else if plates != gates {
+            return Err(format!("Plates and gates must be linked in pairs"))
+        }
+        if let Some(c) = (0..doors.len()).find(|&c| doors[c] && !keys[c]) {
+            return Err(format!("No key for the {} doors", theme::PALETTE[c].0))
         }
-        let state = State {
+        for pair in pads.iter().filter(|p| !p.is_empty()) {
+            if pair.len() != 2 {
+                return Err(format!("Teleporters must be linked in pairs"))
+            }
+            for k in 0..2 {
+                let (i, j) = pair[k];
+                let (to, other) = (pair[1 - k], &mut map[j as usize][i as usize]);
+                other.teleport = other.teleport.map(|pad| Pad { to: to, ..pad });
+            }
+        }
+        let mut state = State {
             data: map,
-            player: Player { x: x, y: y },
+            player: starts.remove(0),
+            others: starts,
             solved: false,
-            goals_left: num_goals
+            goals_left: goals,
+            gates: vec![false; num_ids],
+            keys: vec![0; theme::PALETTE.len()],
+            moves: 0,
+            pushes: 0
         };
-        Ok((width, height, state))
+        state.gates = state.open_gates(true, None);
+        Ok((width, height, state, all_exit))
     }
 
     pub fn new(path: &str, cell_size: u32, max_undo: usize) -> Result<Map, String> {
-
         let f = match File::open(path) {
             Ok(f) => f,
             Err(e) =>
@@ -257,16 +870,22 @@ impl Map {
                     return Err(format!("Invalid line in '{}': {}\n", path, e))
             }
         }
-        let (width, height, state) = match Map::load(&lines) {
-            Ok(d) => d,
-            Err(e) => return Err(e)
+        let name = match Path::new(path).file_stem() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => path.to_string()
         };
-        let mut states = Vec::new();
-        states.push(state);
+        Map::from_lines(name, lines, cell_size, max_undo)
+    }
+
+    /* Map from the lines of a map file */
+    fn from_lines(name: String, lines: Vec<String>, cell_size: u32, max_undo: usize)
+                  -> Result<Map, String> {
+        let (width, height, state, all_exit) = Map::load(&lines)?;
+        let states = vec![state];
         Ok(Map {
-            width: width, height: height, states: states,
-            cell_size: cell_size, max_undo: max_undo,
-            lines: lines
+            name: name, width: width, height: height, states: states,
+            cell_size: cell_size, max_undo: max_undo, all_exit: all_exit,
+            lines: lines, last_move: None
         })
     }
 
@@ -289,6 +908,18 @@ impl Map {
         let h: usize = self.height as usize;
         let x: usize = state.player.x as usize;
         let y: usize = state.player.y as usize;
+        let pushes = state.pushes;
+        /* what would be pushed, it may fall into a hole */
+        let pushed = {
+            let (dx, dy) = dir.delta();
+            let (i, j) = (x as i32 + dx, y as i32 + dy);
+            if self.inside(i, j) {
+                let cell = state.data[j as usize][i as usize];
+                Cell { block_color: cell.block_color, ..Cell::non_goal(cell.kind) }
+            } else {
+                Cell::non_goal(CellType::Wall)
+            }
+        };
         let moved = {
             match dir {
                 Direction::Up => if y > 0 {
@@ -316,19 +947,71 @@ impl Map {
 
         /* If we moved, update the undo stack */
         if moved {
+            let from = (x as i32, y as i32);
+            let (dx, dy) = dir.delta();
+            let block = if state.pushes != pushes {
+                /* the pushed block slides first, then the player */
+                let start = (state.player.x + dx, state.player.y + dy);
+                let mut end = start;
+                if state.data[start.1 as usize][start.0 as usize].is_movable() {
+                    end = state.slide(start.0, start.1, dir, true, |x, y| state.is_free(x, y));
+                    if end != start {
+                        state.move_block(start.0 as usize, start.1 as usize,
+                                         end.0 as usize, end.1 as usize)
+                    }
+                    if let Some(hole) = state.falls(end.0, end.1, dir) {
+                        state.fill_hole(end.0 as usize, end.1 as usize,
+                                        hole.0 as usize, hole.1 as usize);
+                        end = hole
+                    } else {
+                        /* the block is seen going onto the pad, not beyond */
+                        let player = (state.player.x, state.player.y);
+                        let to = state.teleport(end.0, end.1, true, |x, y| {
+                            (x, y) != player && state.is_free(x, y)
+                        });
+                        if to != end {
+                            state.move_block(end.0 as usize, end.1 as usize,
+                                             to.0 as usize, to.1 as usize)
+                        }
+                    }
+                }
+                Some(((state.player.x, state.player.y), end, pushed))
+            } else {
+                None
+            };
+            state.solved = state.remaining() == 0;
+            let to = state.slide(state.player.x, state.player.y, dir, false,
+                                 |x, y| state.is_free(x, y));
+            let dest = state.teleport(to.0, to.1, false, |x, y| state.is_free(x, y));
+            state.player.x = dest.0;
+            state.player.y = dest.1;
+            state.pick_key();
+            state.gates = state.open_gates(true, None);
+            self.last_move = Some(Motion { from: from, to: to, block: block });
+            state.player.facing = dir;
+            state.moves = state.moves + 1;
             if len >= self.max_undo {
                 self.states.remove(0);
             }
             self.states.push(state);
+        } else {
+            /* Bumping into something still turns the player around */
+            self.get_state().player.facing = dir;
         }
 
+        let all_exit = self.all_exit;
         let curr_state = self.get_state();
-        if curr_state.goals_left == 0 {
+        if curr_state.remaining() == 0 {
             curr_state.solved = true;
             let fx : usize = curr_state.player.x as usize;
             let fy : usize = curr_state.player.y as usize;
             if curr_state.data[fy][fx].is_exit() {
-                return true
+                if !all_exit || curr_state.others.is_empty() {
+                    return true
+                }
+                /* this one is out, the next player takes over */
+                curr_state.player = curr_state.others.remove(0);
+                self.last_move = None
             }
         } else {
             curr_state.solved = false
@@ -336,27 +1019,342 @@ impl Map {
         return false
     }
 
-    pub fn render(&self, canvas: & mut sdl2::render::WindowCanvas) {
-        let cs : i32 = self.cell_size as i32;
+    /* Move in dir until the next step would push something or bump into a
+     * wall. The whole run is a single undo step */
+    pub fn run(&mut self, dir: Direction) -> bool {
+        let (dx, dy) = dir.delta();
+        let from = self.player();
+        let max_undo = self.max_undo;
+        self.max_undo = usize::MAX;
+        let mut steps = 0;
+        let mut done = false;
+        let players = self.get_state_ro().others.len();
+        while !done && self.get_state_ro().others.len() == players {
+            let (x, y) = self.player();
+            let (nx, ny) = (x + dx, y + dy);
+            let free = {
+                let state = self.get_state_ro();
+                self.inside(nx, ny) && state.is_free(nx, ny) &&
+                    state.passes((x, y), (nx, ny), false)
+            };
+            if !free {
+                break
+            }
+            let len = self.states.len();
+            done = self.update(dir);
+            if self.states.len() == len && self.player() == (x, y) {
+                /* refused after all, there is no new state to drop */
+                break
+            }
+            steps = steps + 1;
+            if steps > 1 {
+                /* drop the intermediate state */
+                let len = self.states.len();
+                self.states.remove(len - 2);
+            }
+        }
+        self.max_undo = max_undo;
+        while self.states.len() > max_undo {
+            self.states.remove(0);
+        }
+        if self.get_state_ro().others.len() != players {
+            /* the runner left through the exit */
+            self.last_move = None
+        } else if steps > 0 {
+            self.last_move = Some(Motion { from: from, to: self.player(), block: None });
+        } else {
+            /* Bumping into something still turns the player around */
+            self.get_state().player.facing = dir;
+        }
+        done
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.get_state_ro();
+        Stats {
+            moves: state.moves,
+            pushes: state.pushes,
+            goals_left: state.remaining(),
+            undo_depth: self.states.len() - 1
+        }
+    }
+
+    /* Keys held by the player: how many of each color */
+    pub fn keys(&self) -> Vec<(usize, u32)> {
+        let state = self.get_state_ro();
+        (0..state.keys.len()).filter(|&c| state.keys[c] > 0)
+            .map(|c| (c, state.keys[c])).collect()
+    }
+
+    /* The next player takes over, the current one waits last in line. Not
+     * a move, so not an undo step either */
+    pub fn switch(&mut self) {
+        let state = self.get_state();
+        if !state.others.is_empty() {
+            let next = state.others.remove(0);
+            let current = mem::replace(&mut state.player, next);
+            state.others.push(current);
+            self.last_move = None
+        }
+    }
+
+    pub fn player(&self) -> (i32, i32) {
+        let state = self.get_state_ro();
+        (state.player.x, state.player.y)
+    }
+
+    fn inside(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height
+    }
+
+    /* Shortest walks from start to every cell, None for unreachable ones.
+     * free tells which cells can be walked on, and the player slides on
+     * ice. The exit can be reached but not walked through, as entering it
+     * ends the level */
+    fn walk_steps<F>(&self, start: (i32, i32), free: F) -> Vec<Vec<Option<Step>>>
+        where F: Fn(i32, i32) -> bool {
+        let state = self.get_state_ro();
+        let mut steps = vec![vec![None; self.width as usize]; self.height as usize];
+        steps[start.1 as usize][start.0 as usize] =
+            Some(Step { dist: 0, from: start, dir: Direction::Down });
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some((x, y)) = queue.pop_front() {
+            let d = steps[y as usize][x as usize].unwrap().dist;
+            if (x, y) != start && state.data[y as usize][x as usize].is_exit() {
+                continue
+            }
+            for dir in DIRECTIONS.iter() {
+                let (dx, dy) = dir.delta();
+                let (nx, ny) = (x + dx, y + dy);
+                if !self.inside(nx, ny) || !free(nx, ny) ||
+                    !state.passes((x, y), (nx, ny), false) {
+                    continue
+                }
+                let (i, j) = state.slide(nx, ny, *dir, false, &free);
+                let (i, j) = state.teleport(i, j, false, &free);
+                let cell = &mut steps[j as usize][i as usize];
+                if cell.is_none() {
+                    *cell = Some(Step { dist: d + 1, from: (x, y), dir: *dir });
+                    queue.push_back((i, j))
+                }
+            }
+        }
+        steps
+    }
+
+    /* Moves of the walk to target, as computed by walk_steps */
+    fn walk_path(&self, steps: &[Vec<Option<Step>>], target: (i32, i32))
+                 -> Option<Vec<Direction>> {
+        let mut step = steps[target.1 as usize][target.0 as usize]?;
+        let mut path = Vec::new();
+        while step.dist > 0 {
+            path.push(step.dir);
+            step = steps[step.from.1 as usize][step.from.0 as usize].unwrap();
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /* Walking distance from the player to every cell, without pushing
+     * anything. None for unreachable cells */
+    pub fn distances(&self) -> Vec<Vec<Option<u32>>> {
+        let steps = self.walks();
+        steps.iter().map(|row| row.iter().map(|s| s.map(|s| s.dist)).collect())
+            .collect()
+    }
+
+    /* Shortest walk to (x, y) that doesn't push anything */
+    pub fn walk_to(&self, x: i32, y: i32) -> Option<Vec<Direction>> {
+        let steps = self.walks();
+        self.walk_path(&steps, (x, y))
+    }
+
+    /* Walks from the player. Gates only open because of the player are
+     * seen closed, as they close once it walks away */
+    fn walks(&self) -> Vec<Vec<Option<Step>>> {
+        let state = self.get_state_ro();
+        let gates = state.open_gates(false, None);
+        self.walk_steps((state.player.x, state.player.y), |x, y| {
+            state.data[y as usize][x as usize].is_free(state.solved, &gates) &&
+                !state.occupied(x, y)
+        })
+    }
+
+    /* Can the thing at (x, y) be pushed around? */
+    pub fn is_movable(&self, x: i32, y: i32) -> bool {
+        self.inside(x, y) &&
+            self.get_state_ro().data[y as usize][x as usize].is_movable()
+    }
+
+    /* Moves bringing the block at from to target, pushing only this block:
+     * fewest pushes first, with the player walking around the block between
+     * them. None if it can't be done */
+    pub fn push_to(&self, from: (i32, i32), target: (i32, i32))
+                   -> Option<Vec<Direction>> {
+        if !self.is_movable(from.0, from.1) || !self.inside(target.0, target.1) {
+            return None
+        }
+        let state = self.get_state_ro();
+        /* where the block is, the other blocks don't move. Gates only open
+         * because of the player or this block are seen closed */
+        let gates = state.open_gates(false, Some(from));
+        let free = |x: i32, y: i32, block: (i32, i32)| {
+            (x, y) != block && !state.occupied(x, y) &&
+                ((x, y) == from ||
+                 state.data[y as usize][x as usize].is_free(state.solved, &gates))
+        };
+        /* search over the positions of the block and the player after each
+         * push, keeping the one before and the moves in between */
+        type Place = ((i32, i32), (i32, i32));
+        let mut parent: HashMap<Place, (Option<Place>, Vec<Direction>)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((from, (state.player.x, state.player.y), None));
+        while let Some((block, player, place)) = queue.pop_front() {
+            if block == target {
+                /* rebuild the moves from the last place */
+                let mut moves = Vec::new();
+                let mut place = place;
+                while let Some(p) = place {
+                    let &(prev, ref steps) = &parent[&p];
+                    moves.splice(0..0, steps.iter().cloned());
+                    place = prev;
+                }
+                return Some(moves)
+            }
+            let steps = self.walk_steps(player, |x, y| free(x, y, block));
+            for dir in DIRECTIONS.iter() {
+                let (dx, dy) = dir.delta();
+                let behind = (block.0 - dx, block.1 - dy);
+                let ahead = (block.0 + dx, block.1 + dy);
+                if !self.inside(behind.0, behind.1) || !self.inside(ahead.0, ahead.1) ||
+                    state.data[behind.1 as usize][behind.0 as usize].is_exit() ||
+                    !state.passes(behind, block, false) || !state.passes(block, ahead, true) {
+                    continue
+                }
+                let into_hole = state.data[ahead.1 as usize][ahead.0 as usize].is_hole();
+                if !into_hole && !free(ahead.0, ahead.1, block) {
+                    continue
+                }
+                let mut moves = match self.walk_path(&steps, behind) {
+                    Some(m) => m,
+                    None => continue
+                };
+                /* the block slides, then the player behind it */
+                let end = if into_hole {
+                    ahead
+                } else {
+                    let end = state.slide(ahead.0, ahead.1, *dir, true, |x, y| free(x, y, block));
+                    match state.falls(end.0, end.1, *dir) {
+                        Some(hole) => hole,
+                        None => state.teleport(end.0, end.1, true, |x, y| free(x, y, block))
+                    }
+                };
+                /* a block in a hole is gone, fine if it was the target */
+                if state.data[end.1 as usize][end.0 as usize].is_hole() && end != target {
+                    continue
+                }
+                let player = state.slide(block.0, block.1, *dir, false, |x, y| free(x, y, end));
+                let next = (end, state.teleport(player.0, player.1, false,
+                                                |x, y| free(x, y, end)));
+                if parent.contains_key(&next) {
+                    continue
+                }
+                moves.push(*dir);
+                parent.insert(next, (place, moves));
+                queue.push_back((next.0, next.1, Some(next)))
+            }
+        }
+        None
+    }
+
+    /* Cell under the pixel (x, y), if the board is drawn there */
+    pub fn cell_at(&self, area: Rect, view: View, x: i32, y: i32) -> Option<(i32, i32)> {
+        if !area.contains_point((x, y)) || x < view.x || y < view.y {
+            return None
+        }
+        let i = (x - view.x) / view.cell as i32;
+        let j = (y - view.y) / view.cell as i32;
+        if i < self.width && j < self.height {
+            Some((i, j))
+        } else {
+            None
+        }
+    }
+
+    /* View showing the whole map in area: as big as possible, and centered.
+     * With integer scaling, the cell size is a multiple of the configured
+     * one, unless the area is too small */
+    pub fn fit(&self, area: Rect, integer: bool) -> View {
+        let fit = cmp::min(area.width() / self.width as u32,
+                           area.height() / self.height as u32);
+        let cs = if integer && fit >= self.cell_size {
+            fit - fit % self.cell_size
+        } else {
+            cmp::max(fit, 1)
+        };
+        let x = area.x() + (area.width() as i32 - cs as i32 * self.width) / 2;
+        let y = area.y() + (area.height() as i32 - cs as i32 * self.height) / 2;
+        View { x: x, y: y, cell: cs }
+    }
+
+    /* Draw the map as seen through view, clipped to area. progress (from
+     * 0 to 1) is how far the last move is along its animation */
+    pub fn render(&self, canvas: & mut sdl2::render::WindowCanvas, skin: &Skin,
+                  area: Rect, view: View, progress: f32) {
+        let (x0, y0, size) = (view.x, view.y, view.cell);
+        let cs : i32 = size as i32;
         let state = self.get_state_ro();
-        for j in 0..self.height {
-            for i in 0..self.width {
-                let cell = &state.data[j as usize][i as usize];
-                canvas.set_draw_color(cell.color(state.goals_left));
-                canvas.fill_rect(Rect::new(i * cs, j * cs,
-                                           self.cell_size, self.cell_size)).unwrap();
+        let motion = if progress < 1.0 { self.last_move } else { None };
+        let moving_block = match motion {
+            Some(Motion { block: Some((_, to, _)), .. }) => Some(to),
+            _ => None
+        };
+        /* position of an object moving from a to b, in pixels */
+        let lerp = |a: (i32, i32), b: (i32, i32)| {
+            let x = a.0 as f32 + (b.0 - a.0) as f32 * progress;
+            let y = a.1 as f32 + (b.1 - a.1) as f32 * progress;
+            Rect::new(x0 + (x * cs as f32) as i32, y0 + (y * cs as f32) as i32,
+                      size, size)
+        };
+        /* only the visible cells */
+        let i0 = cmp::max(0, (area.x() - x0) / cs);
+        let j0 = cmp::max(0, (area.y() - y0) / cs);
+        let i1 = cmp::min(self.width, (area.right() - x0) / cs + 1);
+        let j1 = cmp::min(self.height, (area.bottom() - y0) / cs + 1);
+        canvas.set_clip_rect(area);
+        for j in j0..j1 {
+            for i in i0..i1 {
+                let mut cell = state.data[j as usize][i as usize];
+                if moving_block == Some((i, j)) {
+                    /* the block is drawn on its way below */
+                    cell.kind = CellType::Empty
+                }
+                let r = Rect::new(x0 + i * cs, y0 + j * cs, size, size);
+                draw_cell(canvas, skin, &cell, state, r, false);
             }
         }
-        /* Draw player */
-        canvas.set_draw_color(Color::RGB(255, 51, 51));
-        canvas.fill_rect(Rect::new(state.player.x * cs,
-                                   state.player.y * cs,
-                                   self.cell_size, self.cell_size)).unwrap();
+        if let Some(Motion { block: Some((from, to, cell)), .. }) = motion {
+            draw_cell(canvas, skin, &cell, state, lerp(from, to), true);
+        }
+        /* Draw players, the waiting ones shaded */
+        for p in state.others.iter() {
+            let r = Rect::new(x0 + p.x * cs, y0 + p.y * cs, size, size);
+            draw_player(canvas, skin, p, r, true)
+        }
+        let r = match motion {
+            Some(m) => lerp(m.from, m.to),
+            None => Rect::new(x0 + state.player.x * cs, y0 + state.player.y * cs,
+                              size, size)
+        };
+        draw_player(canvas, skin, &state.player, r, false);
+        canvas.set_clip_rect(None);
     }
 
     pub fn undo(&mut self) -> bool {
         if self.states.len() > 1 {
             self.states.pop();
+            self.last_move = None;
             true
         } else {
             false
@@ -365,10 +1363,502 @@ impl Map {
 
     pub fn reset(&mut self) {
         let state = match Map::load(&self.lines) {
-            Ok((_, _, s)) => s,
+            Ok((_, _, s, _)) => s,
             Err(e) => panic!("Map reset should not fail: {}\n", e)
         };
         self.states.clear();
         self.states.push(state);
+        self.last_move = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(lines: &[&str]) -> Result<Map, String> {
+        let lines = lines.iter().map(|l| l.to_string()).collect();
+        Map::from_lines("test".to_string(), lines, 32, 100)
+    }
+
+    fn map(lines: &[&str]) -> Map {
+        match load(lines) {
+            Ok(m) => m,
+            Err(e) => panic!("invalid test map: {}", e)
+        }
+    }
+
+    fn moves(m: &mut Map, dirs: &[Direction]) -> bool {
+        let mut done = false;
+        for &dir in dirs {
+            done = m.update(dir)
+        }
+        done
+    }
+
+    use self::Direction::{Down, Left, Right, Up};
+
+    #[test]
+    fn walk_goes_around_blocks() {
+        let mut m = map(&["8", "5",
+                          "........",
+                          ".s b  x.",
+                          ". .. g .",
+                          ".      .",
+                          "........"]);
+        let path = m.walk_to(5, 1).expect("no walk found");
+        assert_eq!(path.len(), 8);
+        moves(&mut m, &path);
+        assert_eq!(m.player(), (5, 1));
+        assert_eq!(m.stats().pushes, 0);
+        assert!(m.is_movable(3, 1));
+    }
+
+    #[test]
+    fn walk_refuses_walls_and_blocks() {
+        let m = map(&["8", "5",
+                      "........",
+                      ".s b  x.",
+                      ". .. g .",
+                      ".      .",
+                      "........"]);
+        assert_eq!(m.walk_to(2, 2), None);
+        assert_eq!(m.walk_to(3, 1), None);
+        assert_eq!(m.walk_to(1, 1), Some(Vec::new()));
+    }
+
+    #[test]
+    fn walk_slides_on_ice() {
+        let m = map(&["8", "4",
+                      "........",
+                      ".siii  .",
+                      ". b g x.",
+                      "........"]);
+        assert_eq!(m.walk_to(5, 1), Some(vec![Right]));
+        /* the middle of the ice can only be reached from below, going
+         * around the block */
+        assert_eq!(m.walk_to(3, 1), Some(vec![Right, Down, Left, Left, Up]));
+    }
+
+    #[test]
+    fn push_around_a_corner() {
+        let mut m = map(&["8", "6",
+                          "........",
+                          ".s     .",
+                          ".  b   .",
+                          ".      .",
+                          ".    gx.",
+                          "........"]);
+        let path = m.push_to((3, 2), (5, 4)).expect("no push found");
+        moves(&mut m, &path);
+        assert!(m.is_movable(5, 4));
+        assert_eq!(m.stats().pushes, 4);
+        assert_eq!(m.stats().goals_left, 0);
+    }
+
+    #[test]
+    fn push_across_ice_into_a_hole() {
+        let lines = ["9", "5",
+                     ".........",
+                     ".s      .",
+                     ". biio  .",
+                     ".   g  x.",
+                     "........."];
+        let mut m = map(&lines);
+        let path = m.push_to((2, 2), (5, 2)).expect("no push found");
+        assert_eq!(path, vec![Down, Right]);
+        moves(&mut m, &path);
+        assert_eq!(m.stats().pushes, 1);
+        assert!(!m.is_movable(5, 2));
+        /* the hole is floor now */
+        assert!(m.walk_to(5, 2).is_some());
+    }
+
+    #[test]
+    fn push_refuses_unreachable_targets() {
+        let m = map(&["9", "5",
+                      ".........",
+                      ".s      .",
+                      ". biio  .",
+                      ".   g  x.",
+                      "........."]);
+        /* the block can't stop on the ice, nor go through walls */
+        assert_eq!(m.push_to((2, 2), (4, 2)), None);
+        assert_eq!(m.push_to((2, 2), (2, 0)), None);
+        /* nor can floor be dragged */
+        assert_eq!(m.push_to((1, 1), (2, 1)), None);
+    }
+
+    #[test]
+    fn run_stops_before_pushing() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".s  b x.",
+                          ". g    .",
+                          "........"]);
+        assert!(!m.run(Right));
+        assert_eq!(m.player(), (3, 1));
+        assert_eq!(m.stats().moves, 2);
+        assert_eq!(m.stats().pushes, 0);
+    }
+
+    #[test]
+    fn run_is_one_undo_step() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".s    x.",
+                          ". b g  .",
+                          "........"]);
+        assert!(!m.run(Right));
+        /* the exit is closed */
+        assert_eq!(m.player(), (5, 1));
+        assert_eq!(m.stats().undo_depth, 1);
+        assert!(m.undo());
+        assert_eq!(m.player(), (1, 1));
+        assert!(!m.undo());
+    }
+
+    #[test]
+    fn run_stops_before_an_arrow_the_wrong_way() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".s  < x.",
+                          ".  bg  .",
+                          "........"]);
+        assert!(!m.run(Right));
+        assert_eq!(m.player(), (3, 1));
+        assert_eq!(m.stats().undo_depth, 1);
+        assert!(!m.run(Right));
+        assert_eq!(m.stats().undo_depth, 1);
+        assert!(m.undo());
+        assert_eq!(m.player(), (1, 1));
+    }
+
+    #[test]
+    fn ice_slide_is_one_move() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".siii  .",
+                          ". b g x.",
+                          "........"]);
+        m.update(Right);
+        assert_eq!(m.player(), (5, 1));
+        assert_eq!(m.stats().moves, 1);
+        assert!(m.undo());
+        assert_eq!(m.player(), (1, 1));
+    }
+
+    #[test]
+    fn pushed_block_slides_on_ice() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".sbii  .",
+                          ".  g  x.",
+                          "........"]);
+        m.update(Right);
+        assert!(m.is_movable(5, 1));
+        assert_eq!(m.player(), (2, 1));
+        assert!(m.undo());
+        assert!(m.is_movable(2, 1));
+    }
+
+    #[test]
+    fn gate_opens_with_its_plate() {
+        let mut m = map(&["7", "4",
+                          ".......",
+                          ".sb1!x.",
+                          ".  g  .",
+                          ".......",
+                          "1 plate A",
+                          "! gate A"]);
+        assert!(!m.get_state_ro().gates[0]);
+        m.update(Right);
+        assert!(m.get_state_ro().gates[0]);
+        assert!(m.undo());
+        assert!(!m.get_state_ro().gates[0]);
+    }
+
+    #[test]
+    fn gates_need_plates() {
+        assert!(load(&["7", "4",
+                       ".......",
+                       ".sb !x.",
+                       ".  g  .",
+                       ".......",
+                       "! gate A"]).is_err());
+    }
+
+    #[test]
+    fn hole_swallows_a_block() {
+        let mut m = map(&["7", "4",
+                          ".......",
+                          ".sbo x.",
+                          ". bg  .",
+                          "......."]);
+        m.update(Right);
+        assert!(!m.is_movable(3, 1));
+        assert_eq!(m.stats().pushes, 1);
+        /* the hole is floor now */
+        m.update(Right);
+        assert_eq!(m.player(), (3, 1));
+        m.undo();
+        m.undo();
+        assert!(m.is_movable(2, 1));
+    }
+
+    #[test]
+    fn spare_blocks_need_holes() {
+        assert!(load(&["7", "4",
+                       ".......",
+                       ".sb  x.",
+                       ". bg  .",
+                       "......."]).is_err());
+    }
+
+    #[test]
+    fn key_opens_its_door() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".skK  x.",
+                          ". b g  .",
+                          "........",
+                          "k key red",
+                          "K door red"]);
+        m.update(Right);
+        assert_eq!(m.keys(), vec![(0, 1)]);
+        m.update(Right);
+        assert_eq!(m.player(), (3, 1));
+        assert!(m.keys().is_empty());
+        m.undo();
+        assert_eq!(m.player(), (2, 1));
+        assert_eq!(m.keys(), vec![(0, 1)]);
+        m.undo();
+        assert!(m.keys().is_empty());
+    }
+
+    #[test]
+    fn block_on_a_key_keeps_its_glyph() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".sbkK x.",
+                          ".   g  .",
+                          "........",
+                          "k key red",
+                          "K door red"]);
+        m.update(Right);
+        let state = m.get_state_ro();
+        let cell = &state.data[1][3];
+        assert!(cell.key.is_some() && cell.is_movable());
+        match cell.glyph(state) {
+            Some(Glyph::Square) => {},
+            _ => panic!("block glyph lost")
+        }
+        /* doors get their lock instead */
+        assert!(state.data[1][4].glyph(state).is_none());
+    }
+
+    #[test]
+    fn door_stays_locked_without_key() {
+        let mut m = map(&["8", "4",
+                          "........",
+                          ".sK k x.",
+                          ". b g  .",
+                          "........",
+                          "k key red",
+                          "K door red"]);
+        m.update(Right);
+        assert_eq!(m.player(), (1, 1));
+        assert!(load(&["8", "4",
+                       "........",
+                       ".sK   x.",
+                       ". b g  .",
+                       "........",
+                       "K door red"]).is_err());
+    }
+
+    #[test]
+    fn teleport_moves_the_player() {
+        let mut m = map(&["9", "4",
+                          ".........",
+                          ".s1b 2 x.",
+                          ".   g   .",
+                          ".........",
+                          "1 teleport A",
+                          "2 teleport A"]);
+        m.update(Right);
+        assert_eq!(m.player(), (5, 1));
+        m.update(Left);
+        assert_eq!(m.player(), (4, 1));
+        /* the block doesn't go through */
+        m.update(Left);
+        assert_eq!(m.player(), (3, 1));
+        assert!(m.is_movable(2, 1));
+    }
+
+    #[test]
+    fn teleport_sends_blocks_when_told() {
+        let mut m = map(&["9", "4",
+                          ".........",
+                          ".s1b 2 x.",
+                          ".   g   .",
+                          ".........",
+                          "1 teleport A blocks",
+                          "2 teleport A blocks"]);
+        moves(&mut m, &[Right, Left, Left]);
+        assert_eq!(m.player(), (3, 1));
+        assert!(m.is_movable(5, 1));
+        assert!(!m.is_movable(2, 1));
+    }
+
+    #[test]
+    fn teleport_blocked_when_partner_taken() {
+        let mut m = map(&["10", "4",
+                          "..........",
+                          ".s1  b2 x.",
+                          ".    g   .",
+                          "..........",
+                          "1 teleport A",
+                          "2 teleport A"]);
+        moves(&mut m, &[Down, Right, Right, Right, Up, Right]);
+        assert!(m.is_movable(6, 1));
+        moves(&mut m, &[Left, Left, Left]);
+        assert_eq!(m.player(), (2, 1));
+    }
+
+    #[test]
+    fn teleports_come_in_pairs() {
+        assert!(load(&["9", "4",
+                       ".........",
+                       ".s1b   x.",
+                       ".   g   .",
+                       ".........",
+                       "1 teleport A"]).is_err());
+    }
+
+    #[test]
+    fn arrows_are_one_way() {
+        let mut m = map(&["7", "4",
+                          ".......",
+                          ".s> bx.",
+                          ".   g .",
+                          "......."]);
+        m.update(Right);
+        assert_eq!(m.player(), (2, 1));
+        /* can't go back against the arrow, nor enter it from the side */
+        m.update(Left);
+        assert_eq!(m.player(), (2, 1));
+        m.update(Down);
+        m.update(Up);
+        assert_eq!(m.player(), (2, 2));
+    }
+
+    #[test]
+    fn blocks_follow_arrows_only_when_told() {
+        let mut lines = vec!["7", "4",
+                             ".......",
+                             ".sba  .",
+                             ".  g x.",
+                             ".......",
+                             "a arrow left"];
+        let mut m = map(&lines);
+        m.update(Right);
+        assert!(m.is_movable(3, 1));
+        lines[6] = "a arrow left blocks";
+        let mut m = map(&lines);
+        m.update(Right);
+        assert!(m.is_movable(2, 1));
+        assert_eq!(m.player(), (1, 1));
+    }
+
+    #[test]
+    fn colored_block_counts_on_its_color_only() {
+        let lines = ["9", "4",
+                     ".........",
+                     ".sRg    .",
+                     ".  r b x.",
+                     ".........",
+                     "R block red",
+                     "r goal red"];
+        let mut m = map(&lines);
+        m.update(Right);
+        assert_eq!(m.stats().goals_left, 2);
+        assert_eq!(m.get_state_ro().goals_left[class(Some(0))], 1);
+
+        let mut m = map(&[lines[0], lines[1], lines[2], ".sRr    .",
+                          ".  g b x.", lines[5], lines[6], lines[7]]);
+        m.update(Right);
+        assert_eq!(m.stats().goals_left, 1);
+        assert_eq!(m.get_state_ro().goals_left[class(Some(0))], 0);
+        assert_eq!(m.get_state_ro().goals_left[class(None)], 1);
+        m.undo();
+        assert_eq!(m.stats().goals_left, 2);
+    }
+
+    #[test]
+    fn goals_need_blocks_of_their_color() {
+        assert!(load(&["9", "4",
+                       ".........",
+                       ".sbr    .",
+                       ".  g b x.",
+                       ".........",
+                       "r goal red"]).is_err());
+    }
+
+    const PLAYERS: [&str; 4] = [".......",
+                                ".sbg x.",
+                                ".s    .",
+                                "......."];
+
+    fn players(rule: &str) -> Map {
+        let mut lines = vec!["7", "4"];
+        lines.extend(PLAYERS.iter());
+        lines.push(rule);
+        let mut m = map(&lines);
+        /* the second player makes room, the first fills the goal */
+        m.switch();
+        m.update(Right);
+        m.switch();
+        m.update(Right);
+        assert_eq!(m.stats().goals_left, 0);
+        m
+    }
+
+    #[test]
+    fn players_block_each_other() {
+        let mut m = map(&["7", "4", PLAYERS[0], PLAYERS[1], PLAYERS[2], PLAYERS[3]]);
+        m.update(Down);
+        assert_eq!(m.player(), (1, 1));
+        m.switch();
+        assert_eq!(m.player(), (1, 2));
+        m.switch();
+        assert_eq!(m.player(), (1, 1));
+    }
+
+    #[test]
+    fn any_player_can_exit() {
+        let mut m = players("exit any");
+        m.switch();
+        assert!(!m.run(Right));
+        assert!(m.update(Up));
+    }
+
+    #[test]
+    fn all_players_must_exit() {
+        let mut m = players("exit all");
+        m.switch();
+        assert!(!m.run(Right));
+        assert_eq!(m.player(), (5, 2));
+        /* this one leaves, the other takes over */
+        assert!(!m.update(Up));
+        assert_eq!(m.player(), (2, 1));
+        m.undo();
+        assert_eq!(m.player(), (5, 2));
+        assert!(!m.update(Up));
+        /* alone now, switching does nothing */
+        m.switch();
+        assert_eq!(m.player(), (2, 1));
+        m.update(Down);
+        assert!(!m.run(Right));
+        assert!(m.update(Up));
     }
 }
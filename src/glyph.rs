@@ -0,0 +1,151 @@
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::WindowCanvas;
+
+use game::Direction;
+
+/* Shapes drawn on top of cells so that they can be told apart without
+ * relying on colors */
+#[derive(Copy, Clone)]
+pub enum Glyph {
+    Cross,      /* goal */
+    Square,     /* block */
+    Ring,       /* block on goal */
+    Stripes,    /* crate */
+    Bars,       /* closed exit */
+    Arrow,      /* open exit */
+    Diamond,    /* player */
+    Hatch,      /* ice */
+    Dot,        /* pressure plate */
+    Grid,       /* closed gate */
+    Pit,        /* hole */
+    Key,        /* key, in its color */
+    Lock,       /* locked door */
+    Portal,     /* teleporter */
+    OneWay(Direction), /* arrow tile */
+}
+
+/* Black or white, whichever reads better on top of color */
+pub fn contrast(color: Color) -> Color {
+    let (r, g, b) = color.rgb();
+    let luma = 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+    if luma > 128 * 1000 {
+        Color::RGB(0, 0, 0)
+    } else {
+        Color::RGB(255, 255, 255)
+    }
+}
+
+fn polygon(canvas: &mut WindowCanvas, points: &[Point]) {
+    let mut closed = points.to_vec();
+    closed.push(points[0]);
+    canvas.draw_lines(&closed[..]).unwrap();
+}
+
+pub fn draw(canvas: &mut WindowCanvas, glyph: Glyph, cell: Rect, color: Color) {
+    let c = cell.center();
+    let (cx, cy) = (c.x(), c.y());
+    /* half size of the glyph, a quarter of the cell */
+    let r = (cell.width() / 4) as i32;
+    canvas.set_draw_color(color);
+    /* lines are drawn twice, one pixel apart, to make them visible */
+    for d in 0..2 {
+        match glyph {
+            Glyph::Cross => {
+                canvas.draw_line((cx - r + d, cy - r), (cx + r + d, cy + r)).unwrap();
+                canvas.draw_line((cx + r + d, cy - r), (cx - r + d, cy + r)).unwrap();
+            },
+            Glyph::Square => {
+                let s = (2 * (r - d)) as u32;
+                canvas.draw_rect(Rect::new(cx - r + d, cy - r + d, s, s)).unwrap();
+            },
+            Glyph::Ring => {
+                let radius = (r - d) as f64;
+                let points: Vec<Point> = (0..24).map(|i| {
+                    let a = i as f64 * ::std::f64::consts::PI / 12.0;
+                    Point::new(cx + (radius * a.cos()) as i32,
+                               cy + (radius * a.sin()) as i32)
+                }).collect();
+                polygon(canvas, &points);
+            },
+            Glyph::Stripes => {
+                for k in -1..2 {
+                    let y = cy + k * r / 2 + d;
+                    canvas.draw_line((cx - r, y), (cx + r, y)).unwrap();
+                }
+            },
+            Glyph::Bars => {
+                for k in -1..2 {
+                    let x = cx + k * r / 2 + d;
+                    canvas.draw_line((x, cy - r), (x, cy + r)).unwrap();
+                }
+            },
+            Glyph::Arrow => {
+                canvas.draw_line((cx + d, cy - r), (cx + d, cy + r)).unwrap();
+                canvas.draw_line((cx + d, cy - r), (cx - r / 2 + d, cy)).unwrap();
+                canvas.draw_line((cx + d, cy - r), (cx + r / 2 + d, cy)).unwrap();
+            },
+            Glyph::Diamond => {
+                let s = r - d;
+                polygon(canvas, &[Point::new(cx, cy - s), Point::new(cx + s, cy),
+                                  Point::new(cx, cy + s), Point::new(cx - s, cy)]);
+            },
+            Glyph::Dot => {
+                let s = (r - d) as u32;
+                canvas.fill_rect(Rect::new(cx - s as i32 / 2, cy - s as i32 / 2, s, s)).unwrap();
+            },
+            Glyph::Grid => {
+                for k in -1..2 {
+                    let o = k * r / 2 + d;
+                    canvas.draw_line((cx + o, cy - r), (cx + o, cy + r)).unwrap();
+                    canvas.draw_line((cx - r, cy + o), (cx + r, cy + o)).unwrap();
+                }
+            },
+            Glyph::Pit => {
+                for k in 1..3 {
+                    let s = k * r / 2 - d;
+                    canvas.draw_rect(Rect::new(cx - s, cy - s, 2 * s as u32, 2 * s as u32)).unwrap();
+                }
+            },
+            Glyph::Key => {
+                /* bow on the left, then the shaft and a tooth */
+                let s = ::std::cmp::max(r - 2 * d, 1) as u32;
+                canvas.draw_rect(Rect::new(cx - r + d, cy - r / 2 + d, s, s)).unwrap();
+                canvas.draw_line((cx, cy + d), (cx + r, cy + d)).unwrap();
+                canvas.draw_line((cx + r - d, cy), (cx + r - d, cy + r / 2)).unwrap();
+            },
+            Glyph::Lock => {
+                /* keyhole */
+                let s = ::std::cmp::max(r - 2 * d, 1) as u32;
+                canvas.draw_rect(Rect::new(cx - r / 2 + d, cy - r + d, s, s)).unwrap();
+                canvas.draw_line((cx + d, cy), (cx + d, cy + r)).unwrap();
+            },
+            Glyph::OneWay(dir) => {
+                /* chevron pointing the way */
+                let (dx, dy) = dir.delta();
+                let (bx, by) = (cx - dx * (r / 2 - d), cy - dy * (r / 2 - d));
+                canvas.draw_line((bx - dy * r, by - dx * r), (bx + dx * r, by + dy * r)).unwrap();
+                canvas.draw_line((bx + dy * r, by + dx * r), (bx + dx * r, by + dy * r)).unwrap();
+            },
+            Glyph::Portal => {
+                for k in 1..3 {
+                    let radius = (k * r / 2 - d) as f64;
+                    let points: Vec<Point> = (0..24).map(|i| {
+                        let a = i as f64 * ::std::f64::consts::PI / 12.0;
+                        Point::new(cx + (radius * a.cos()) as i32,
+                                   cy + (radius * a.sin()) as i32)
+                    }).collect();
+                    polygon(canvas, &points);
+                }
+            },
+            Glyph::Hatch => {
+                for k in -1..2 {
+                    let x = cx + k * r + d;
+                    canvas.draw_line((x - r / 2, cy + r / 2), (x + r / 2, cy - r / 2)).unwrap();
+                }
+            },
+        }
+    }
+}
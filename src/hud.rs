@@ -0,0 +1,55 @@
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+
+use game::Map;
+use text;
+use theme;
+
+const PADDING: i32 = 4;
+
+/* Height of the HUD bar, on top of the window */
+pub fn height(font: &Font) -> u32 {
+    (2 * font.recommended_line_spacing() + 2 * PADDING) as u32
+}
+
+pub fn format_time(ms: u32) -> String {
+    let s = ms / 1000;
+    format!("{:02}:{:02}", s / 60, s % 60)
+}
+
+pub fn draw(canvas: &mut WindowCanvas, font: &Font, map: &Map, elapsed: u32) {
+    let (w, _) = canvas.output_size().unwrap();
+    canvas.set_draw_color(Color::RGB(32, 32, 32));
+    canvas.fill_rect(Rect::new(0, 0, w, height(font))).unwrap();
+
+    let white = Color::RGB(255, 255, 255);
+    let stats = map.stats();
+    let line = font.recommended_line_spacing();
+    text::draw(canvas, font, &map.name, white, PADDING, PADDING);
+    let time = format_time(elapsed);
+    let tw = font.size_of(&time).map(|(tw, _)| tw as i32).unwrap_or(0);
+    text::draw(canvas, font, &time, white, w as i32 - tw - PADDING, PADDING);
+    let goals = if stats.goals_left > 0 {
+        format!("Goals: {}", stats.goals_left)
+    } else {
+        "Exit open".to_string()
+    };
+    text::draw(canvas, font,
+               &format!("Moves: {}  Pushes: {}  {}  Undo: {}",
+                        stats.moves, stats.pushes, goals, stats.undo_depth),
+               white, PADDING, PADDING + line);
+
+    /* keys held, right aligned, each in its color */
+    let mut x = w as i32 - PADDING;
+    for (c, n) in map.keys().into_iter().rev() {
+        let label = format!(" {} key{} x{}", theme::PALETTE[c].0,
+                            if n > 1 { "s" } else { "" }, n);
+        let lw = font.size_of(&label).map(|(lw, _)| lw as i32).unwrap_or(0);
+        x -= lw;
+        text::draw(canvas, font, &label, theme::palette(c), x, PADDING + line);
+    }
+}
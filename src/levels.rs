@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+
+/* Natural order, so that map10 comes after map9 */
+fn natural_key(name: &str) -> (String, u64, String) {
+    let digits: String = name.chars().rev()
+        .take_while(|c| c.is_ascii_digit()).collect::<String>()
+        .chars().rev().collect();
+    let prefix = name[..name.len() - digits.len()].to_string();
+    let n = digits.parse::<u64>().unwrap_or(0);
+    (prefix, n, name.to_string())
+}
+
+/* All the maps in dir, in natural order */
+pub fn list(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("W: can't list levels in '{}': {}", dir.display(), e);
+            return Vec::new()
+        }
+    };
+    let mut maps: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.path().to_string_lossy().into_owned())
+        .collect();
+    maps.sort_by_key(|p| {
+        let name = Path::new(p).file_name().unwrap().to_string_lossy().into_owned();
+        natural_key(&name)
+    });
+    maps
+}
+
+/* All the maps in the directory of path */
+pub fn siblings(path: &str) -> Vec<String> {
+    match Path::new(path).parent() {
+        Some(d) if d.as_os_str().is_empty() => list(Path::new(".")),
+        Some(d) => list(d),
+        None => Vec::new()
+    }
+}
+
+/* The map after path in its directory, if any */
+pub fn next(path: &str) -> Option<String> {
+    let maps = siblings(path);
+    let current = Path::new(path).file_name();
+    match maps.iter().position(|m| Path::new(m).file_name() == current) {
+        Some(i) => maps.get(i + 1).cloned(),
+        None => None
+    }
+}
+
+/* Where the best run of a level is kept */
+pub fn replay_path(dir: &str, map: &str) -> String {
+    let name = match Path::new(map).file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => map.to_string()
+    };
+    Path::new(dir).join(format!("{}.run", name)).to_string_lossy().into_owned()
+}
@@ -1,21 +1,33 @@
 extern crate sdl2;
 extern crate clap;
 
-use sdl2::pixels::Color;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::rect::Rect;
 use sdl2::image::LoadTexture;
 
 use clap::{Arg, App};
 
+use std::fs;
 use std::path::Path;
 
-const CELL_SIZE : u32 = 32;
+const FONT_PATH : &str = "data/fonts/DejaVuSansMono.ttf";
+const FONT_SIZE : u16 = 16;
 
+mod camera;
+mod endscreen;
 mod game;
+mod glyph;
+mod hud;
 mod config;
+mod levels;
+mod menu;
+mod pause;
+mod play;
 mod record;
+mod score;
+mod settings;
+mod text;
+mod theme;
+mod tileset;
+mod ui;
 
 fn main() {
     let matches = App::new("Sokobad")
@@ -49,39 +61,65 @@ fn main() {
              .help("Run a saved file instead of interactive playing")
              .takes_value(true)
              .conflicts_with("record"))
+        .arg(Arg::with_name("check-config")
+             .long("check-config")
+             .help("Validate the configuration file and exit"))
+        .arg(Arg::with_name("save-config")
+             .long("save-config")
+             .value_name("FILE")
+             .help("Write the effective configuration to FILE and exit")
+             .takes_value(true))
         .get_matches();
 
     let config_path = matches.value_of("config").unwrap_or("data/config.json");
 
     println!("Loading configuration: {}", config_path);
-    let game_conf = match config::new(config_path) {
+    let mut game_conf = match config::new(config_path) {
         Ok(gc) => gc,
         Err(e) => {
             eprintln!("E: {}", e);
             std::process::exit(1)
         }
     };
-    let keys = &game_conf.keys;
+    for w in &game_conf.warnings {
+        eprintln!("W: {}", w);
+    }
+    if matches.is_present("check-config") {
+        println!("Configuration '{}' is valid", config_path);
+        std::process::exit(0)
+    }
+    if let Some(path) = matches.value_of("save-config") {
+        match game_conf.save(path) {
+            Ok(_) => println!("Configuration saved to '{}'", path),
+            Err(e) => {
+                eprintln!("E: {}", e);
+                std::process::exit(1)
+            }
+        }
+        std::process::exit(0)
+    }
     let undo_level = game_conf.undo_level;
-    let speed = game_conf.replay_speed;
+    let cell_size = game_conf.cell_size;
 
-    let record_path = matches.value_of("record").unwrap(); /* has a default value */
-    let mut record = if matches.occurrences_of("record") != 0 {
-        record::Run::new(record_path)
-    } else {
-        record::Run::empty()
+    /* has a default value, but runs are only saved when asked to */
+    let run_files = RunFiles {
+        record: if matches.occurrences_of("record") != 0 {
+            matches.value_of("record")
+        } else {
+            None
+        },
+        save: matches.value_of("record").unwrap()
     };
 
-    let mut replay = false;
+    let mut mode = play::Mode::Play;
     if matches.occurrences_of("play") != 0 {
-        replay = true;
-        record = record::Run::load(matches.value_of("play").unwrap())
+        mode = play::Mode::Replay(record::Run::load(matches.value_of("play").unwrap()))
     }
 
-    let map_path = matches.value_of("map").unwrap(); /* has a default value */
+    let map_path = matches.value_of("map").unwrap().to_string(); /* has a default value */
     println!("Loading map: {}", map_path);
 
-    let mut map = match game::Map::new(map_path, CELL_SIZE, undo_level) {
+    let map = match game::Map::new(&map_path, cell_size, undo_level) {
         Ok(m) => m,
         Err(e) => {
             eprintln!("E: {}", e);
@@ -91,12 +129,26 @@ fn main() {
 
     let sdl = sdl2::init().unwrap();
     let _sdl_image = sdl2::image::init(sdl2::image::INIT_PNG).unwrap();
-    // let _sdl_ttf = sdl2::ttf::init().unwrap();
+    let sdl_ttf = sdl2::ttf::init().unwrap();
+    let font = match sdl_ttf.load_font(Path::new(FONT_PATH), FONT_SIZE) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("E: Failure to load font '{}': {}", FONT_PATH, e);
+            std::process::exit(1)
+        }
+    };
 
     let video_subsystem = sdl.video().unwrap();
 
-    let window_width: u32 = (map.width as u32) * CELL_SIZE;
-    let window_height: u32 = (map.height as u32) * CELL_SIZE;
+    /* Big maps don't fit on screen, the camera will follow the player */
+    let (max_width, max_height) = match video_subsystem.display_bounds(0) {
+        Ok(r) => (r.width() * 9 / 10, r.height() * 9 / 10),
+        Err(_) => (1280, 720)
+    };
+    let window_width: u32 = std::cmp::min((map.width as u32) * cell_size, max_width);
+    let hud_height = if game_conf.hud { hud::height(&font) } else { 0 };
+    let window_height: u32 = std::cmp::min((map.height as u32) * cell_size + hud_height,
+                                           max_height);
 
     let window = video_subsystem
         .window("Sokoban", window_width, window_height)
@@ -105,14 +157,14 @@ fn main() {
         .build()
         .unwrap();
 
-    let mut canvas : sdl2::render::WindowCanvas = window.into_canvas()
+    let canvas : sdl2::render::WindowCanvas = window.into_canvas()
         .accelerated()
         .present_vsync()
         .target_texture()
         .build()
         .unwrap();
 
-    let mut timer_subsystem = sdl.timer().unwrap();
+    let timer_subsystem = sdl.timer().unwrap();
 
     let tex_creator = canvas.texture_creator();
     let tex = match tex_creator.load_texture(Path::new("data/img/win.png")) {
@@ -123,130 +175,152 @@ fn main() {
         }
     };
 
-    let mut events = sdl.event_pump().unwrap();
-    let mut now = timer_subsystem.ticks();
-    let mut done: bool;
-    let mut movements = 0;
-    'main: loop {
-        done = false;
-        let mut cmd = None;
-        for event in events.poll_iter() {
-            if replay {
-                match event {
-                    Event::Quit {..} => {
-                        break 'main
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                        break 'main
-                    },
-                    _ => {},
-                }
-            } else {
-                match event {
-                    Event::Quit {..} => {
-                        record.record(record::Command::Quit);
-                        break 'main
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                        record.record(record::Command::Quit);
-                        break 'main
-                    },
-                    Event::KeyDown { keycode, .. } => {
-                        match keycode {
-                            Some(key) => cmd = cmd_of_key(key, &keys),
-                            None => ()
-                        }
-                    }
-                    _ => {},
-                }
+    let tiles = match game_conf.tileset {
+        Some(ref desc) => match tileset::Tileset::new(&tex_creator, desc) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                eprintln!("E: {}", e);
+                std::process::exit(1)
             }
-        }
+        },
+        None => None
+    };
 
-        if replay {
-            let cur = timer_subsystem.ticks();
-            if cur - now > speed {
-                now = cur;
-                match record.next() {
-                    Some(c) => {
-                        cmd = Some(c)
-                    },
-                    None => break 'main
-                }
-            }
-        }
+    let mut ui = ui::Ui {
+        canvas: canvas,
+        events: sdl.event_pump().unwrap(),
+        timer: timer_subsystem,
+        font: font,
+        tiles: tiles,
+        win: tex
+    };
+    let mut scores = score::Scoreboard::load(&game_conf.scoreboard);
+    let maps = levels::siblings(&map_path);
+    let mut ctx = Context {
+        ui: &mut ui,
+        conf: &mut game_conf,
+        config_path,
+        scores: &mut scores,
+        run_files
+    };
 
-        match cmd {
-            Some(cmd) => {
-                movements = movements + 1;
-                if !replay { record.record(cmd) };
-                match cmd {
-                    record::Command::Quit => break 'main,
-                    record::Command::Up => done = map.update(game::Direction::Up),
-                    record::Command::Down => done = map.update(game::Direction::Down),
-                    record::Command::Left => done = map.update(game::Direction::Left),
-                    record::Command::Right => done = map.update(game::Direction::Right),
-                    record::Command::Undo => {
-                        if map.undo() {
-                            /* to account for the +1 above */
-                            movements = movements - 2
-                        }
-                    },
-                    record::Command::Reset => {
-                        movements = 0;
-                        map.reset()
-                    }
-                }
-            },
-            None => ()
-        }
+    /* an explicit map or run skips the title menu, until quitting to it */
+    if (matches.occurrences_of("map") != 0 || matches.occurrences_of("play") != 0) &&
+        session(&mut ctx, map_path, map, mode) {
+        return
+    }
 
-        /* Render here */
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
+    loop {
+        let (path, mode) = match menu::run(ctx.ui, ctx.conf, ctx.config_path,
+                                           ctx.scores, &maps) {
+            menu::Choice::Play(p) => (p, play::Mode::Play),
+            menu::Choice::Replay(p, r) => (p, play::Mode::Replay(record::Run::load(&r))),
+            menu::Choice::Quit => break
+        };
+        println!("Loading map: {}", path);
+        let map = match game::Map::new(&path, ctx.conf.cell_size, ctx.conf.undo_level) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("E: {}", e);
+                continue
+            }
+        };
+        if session(&mut ctx, path, map, mode) {
+            break
+        }
+    }
+}
 
-        map.render(&mut canvas);
+/* Where runs are written */
+struct RunFiles<'a> {
+    record: Option<&'a str>, /* every run, with -r */
+    save: &'a str /* "save and quit" from the pause menu */
+}
 
-        canvas.present();
+/* What a session needs besides the level it starts with */
+struct Context<'a, 'ttf: 'a, 'r: 'a> {
+    ui: &'a mut ui::Ui<'ttf, 'r>,
+    conf: &'a mut config::Config,
+    config_path: &'a str,
+    scores: &'a mut score::Scoreboard,
+    run_files: RunFiles<'a>
+}
 
-        if done {
-            break 'main
+/* Plays levels one after the other until the player quits, returns true if
+ * the game should be left */
+fn session(ctx: &mut Context, mut map_path: String, mut map: game::Map,
+           mut mode: play::Mode) -> bool {
+    loop {
+        let replay = match mode {
+            play::Mode::Replay(_) => true,
+            play::Mode::Play => false
+        };
+        let outcome = play::run(ctx.ui, ctx.conf, ctx.config_path, &mut map, mode);
+        if !replay {
+            if let Some(path) = ctx.run_files.record {
+                outcome.run.save(path)
+            } else if outcome.exit == play::Exit::Save {
+                outcome.run.save(ctx.run_files.save)
+            }
         }
-    }
-    if !replay {
-        record.save(record_path)
-    }
 
-    if done {
-        let w2 = window_width / 2;
-        let h2 = window_height / 2;
-        let r = Rect::new((w2 - w2 / 2) as i32, (h2 - h2 / 2) as i32, w2, h2);
-        canvas.copy(&tex, None, Some(r)).unwrap();
-        canvas.present();
-        println!("Congratulations, you won !");
-    } else {
-        println!("Sorry, you failed");
-    }
-    println!("Movements: {}", movements);
-    timer_subsystem.delay(2000);
-}
+        if outcome.won {
+            println!("Congratulations, you won !");
+        } else {
+            println!("Sorry, you failed");
+        }
+        println!("Movements: {}", outcome.movements);
+        match outcome.exit {
+            play::Exit::Finished => {},
+            play::Exit::Menu => return false,
+            play::Exit::Save | play::Exit::Closed => return true
+        }
 
+        let ranking = if outcome.won && !replay {
+            let score = score::Score {
+                moves: outcome.stats.moves,
+                pushes: outcome.stats.pushes,
+                time: outcome.elapsed
+            };
+            let rank = ctx.scores.insert(&map_path, score);
+            ctx.scores.save();
+            if rank == Some(1) {
+                /* keep the best run around for the replays menu */
+                let replays = &ctx.conf.replays;
+                match fs::create_dir_all(replays) {
+                    Ok(_) => outcome.run.save(&levels::replay_path(replays, &map_path)),
+                    Err(e) => eprintln!("W: can't create '{}': {}", replays, e)
+                }
+            }
+            match rank {
+                Some(r) => endscreen::Ranking::Ranked(r),
+                None => endscreen::Ranking::Unranked
+            }
+        } else {
+            endscreen::Ranking::Best(ctx.scores.best(&map_path))
+        };
 
-fn cmd_of_key(key: Keycode, keys: &config::KeyBindings) -> Option<record::Command> {
-    if key == keys.quit {
-        Some(record::Command::Quit)
-    } else if key == keys.up {
-        Some(record::Command::Up)
-    } else if key == keys.down {
-        Some(record::Command::Down)
-    } else if key == keys.left {
-        Some(record::Command::Left)
-    } else if key == keys.right {
-        Some(record::Command::Right)
-    } else if key == keys.undo {
-        Some(record::Command::Undo)
-    } else if key == keys.reset {
-        Some(record::Command::Reset)
-    } else {
-        None
+        let next = levels::next(&map_path);
+        mode = play::Mode::Play;
+        match endscreen::run(ctx.ui, &outcome, &map.name, ranking, next.is_some()) {
+            endscreen::Choice::Retry => map.reset(),
+            endscreen::Choice::Next => {
+                map_path = next.unwrap();
+                println!("Loading map: {}", map_path);
+                map = match game::Map::new(&map_path, ctx.conf.cell_size,
+                                           ctx.conf.undo_level) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        eprintln!("E: {}", e);
+                        return false
+                    }
+                }
+            },
+            endscreen::Choice::Replay => {
+                map.reset();
+                mode = play::Mode::Replay(outcome.run.replay())
+            },
+            endscreen::Choice::Quit => return false
+        }
     }
 }
@@ -0,0 +1,264 @@
+extern crate sdl2;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+
+use std::cmp;
+use std::path::Path;
+
+use config;
+use game;
+use hud;
+use levels;
+use score::Scoreboard;
+use settings;
+use text;
+use ui::Ui;
+
+const THUMB_SIZE: u32 = 128;
+const PADDING: i32 = 16;
+
+pub enum Choice {
+    Play(String),           /* map path */
+    Replay(String, String), /* map path, run path */
+    Quit
+}
+
+enum Pick {
+    Selected(usize),
+    Back,
+    Closed
+}
+
+/* Vertical list of items, returns the selected one */
+fn list(ui: &mut Ui, title: &str, items: &[String]) -> Pick {
+    let n = items.len();
+    let mut selected = 0;
+    loop {
+        for event in ui.events.poll_iter() {
+            match event {
+                Event::Quit {..} => return Pick::Closed,
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    match key {
+                        Keycode::Up if n > 0 => selected = (selected + n - 1) % n,
+                        Keycode::Down if n > 0 => selected = (selected + 1) % n,
+                        Keycode::Return if n > 0 => return Pick::Selected(selected),
+                        Keycode::Escape => return Pick::Back,
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let canvas = &mut ui.canvas;
+        let font = &ui.font;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        let white = Color::RGB(255, 255, 255);
+        let line = font.recommended_line_spacing();
+        let (w, h) = canvas.output_size().unwrap();
+        let mut y = 2 * line;
+        text::draw_centered(canvas, font, title, white, y);
+        y += 2 * line;
+        if n == 0 {
+            text::draw_centered(canvas, font, "Nothing here yet", white, y);
+        }
+        /* scroll to keep the selection visible */
+        let visible = cmp::max(1, (h as i32 - y - 2 * line) / line) as usize;
+        let first = if selected >= visible { selected + 1 - visible } else { 0 };
+        for (i, item) in items.iter().enumerate().skip(first).take(visible) {
+            if i == selected {
+                canvas.set_draw_color(Color::RGB(96, 96, 96));
+                canvas.fill_rect(Rect::new(0, y, w, line as u32)).unwrap();
+            }
+            text::draw_centered(canvas, font, item, white, y);
+            y += line;
+        }
+        text::draw_centered(canvas, font, "Enter: select   Escape: back", white,
+                            h as i32 - 2 * line);
+        canvas.present();
+    }
+}
+
+/* Render each map into a texture, None for maps that can't be loaded */
+fn thumbnails<'r>(ui: &mut Ui, conf: &config::Config, maps: &[String],
+                  creator: &'r sdl2::render::TextureCreator<sdl2::video::WindowContext>)
+                  -> Vec<Option<Texture<'r>>> {
+    let skin = game::Skin {
+        theme: &conf.theme,
+        glyphs: conf.shape_glyphs,
+        tileset: ui.tiles.as_ref()
+    };
+    let area = Rect::new(0, 0, THUMB_SIZE, THUMB_SIZE);
+    let mut thumbs = Vec::new();
+    for path in maps {
+        let map = match game::Map::new(path, conf.cell_size, 1) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("W: can't load '{}': {}", path, e);
+                thumbs.push(None);
+                continue
+            }
+        };
+        let mut tex = match creator.create_texture_target(None, THUMB_SIZE, THUMB_SIZE) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("W: can't create thumbnail: {}", e);
+                thumbs.push(None);
+                continue
+            }
+        };
+        let view = map.fit(area, false);
+        let background = conf.theme.background;
+        let res = ui.canvas.with_texture_canvas(&mut tex, |c| {
+            c.set_draw_color(background);
+            c.clear();
+            map.render(c, &skin, area, view, 1.0);
+        });
+        thumbs.push(if res.is_ok() { Some(tex) } else { None });
+    }
+    thumbs
+}
+
+/* Grid of levels with a preview, whether they were solved and their best
+ * score */
+fn level_select(ui: &mut Ui, conf: &config::Config, scores: &Scoreboard,
+                maps: &[String]) -> Pick {
+    let creator = ui.canvas.texture_creator();
+    let thumbs = thumbnails(ui, conf, maps, &creator);
+    let n = maps.len();
+    let mut selected = 0;
+    loop {
+        let (w, h) = ui.canvas.output_size().unwrap();
+        let line = ui.font.recommended_line_spacing();
+        let cell_w = THUMB_SIZE as i32 + PADDING;
+        let cell_h = THUMB_SIZE as i32 + 2 * line + PADDING;
+        let cols = cmp::max(1, (w as i32 - PADDING) / cell_w) as usize;
+
+        for event in ui.events.poll_iter() {
+            match event {
+                Event::Quit {..} => return Pick::Closed,
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    match key {
+                        Keycode::Left if selected > 0 => selected -= 1,
+                        Keycode::Right if selected + 1 < n => selected += 1,
+                        Keycode::Up if selected >= cols => selected -= cols,
+                        Keycode::Down if selected + cols < n => selected += cols,
+                        Keycode::Return if n > 0 => return Pick::Selected(selected),
+                        Keycode::Escape => return Pick::Back,
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let canvas = &mut ui.canvas;
+        let font = &ui.font;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        let white = Color::RGB(255, 255, 255);
+        let top = 3 * line;
+        text::draw_centered(canvas, font, "Select a level", white, line);
+
+        /* scroll to keep the selected row visible */
+        let rows = cmp::max(1, (h as i32 - top) / cell_h) as usize;
+        let row = selected / cols;
+        let first = if row >= rows { row + 1 - rows } else { 0 };
+        let x0 = (w as i32 - cols as i32 * cell_w + PADDING) / 2;
+        for i in (first * cols)..n {
+            let (c, r) = ((i % cols) as i32, (i / cols - first) as i32);
+            if r as usize >= rows {
+                break
+            }
+            let x = x0 + c * cell_w;
+            let y = top + r * cell_h;
+            let frame = Rect::new(x - 4, y - 4, THUMB_SIZE + 8, THUMB_SIZE + 8);
+            if i == selected {
+                canvas.set_draw_color(Color::RGB(255, 255, 51));
+                canvas.fill_rect(frame).unwrap();
+            }
+            let r = Rect::new(x, y, THUMB_SIZE, THUMB_SIZE);
+            match thumbs[i] {
+                Some(ref t) => canvas.copy(t, None, Some(r)).unwrap(),
+                None => {
+                    canvas.set_draw_color(Color::RGB(64, 0, 0));
+                    canvas.fill_rect(r).unwrap();
+                }
+            }
+            let name = match Path::new(&maps[i]).file_name() {
+                Some(n) => n.to_string_lossy().into_owned(),
+                None => maps[i].clone()
+            };
+            let y = y + THUMB_SIZE as i32 + 4;
+            text::draw(canvas, font, &name, white, x, y);
+            let (status, color) = match scores.best(&maps[i]) {
+                Some(s) => (format!("{} moves {}", s.moves, hud::format_time(s.time)),
+                            Color::RGB(103, 240, 139)),
+                None => ("unsolved".to_string(), Color::RGB(192, 192, 192))
+            };
+            text::draw(canvas, font, &status, color, x, y + line);
+        }
+        canvas.present();
+    }
+}
+
+/* Levels with a saved best run */
+fn replay_select(ui: &mut Ui, maps: &[String], replays: &str) -> Option<Choice> {
+    let runs: Vec<(String, String)> = maps.iter()
+        .map(|m| (m.clone(), levels::replay_path(replays, m)))
+        .filter(|(_, r)| Path::new(r).is_file())
+        .collect();
+    let items: Vec<String> = runs.iter().map(|(m, _)| {
+        match Path::new(m).file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => m.clone()
+        }
+    }).collect();
+    match list(ui, "Best runs", &items) {
+        Pick::Selected(i) => Some(Choice::Replay(runs[i].0.clone(), runs[i].1.clone())),
+        Pick::Back => None,
+        Pick::Closed => Some(Choice::Quit)
+    }
+}
+
+/* Title menu, loops until something to play is chosen */
+pub fn run(ui: &mut Ui, conf: &mut config::Config, config_path: &str,
+           scores: &Scoreboard, maps: &[String]) -> Choice {
+    let items: Vec<String> = ["Play", "Continue", "Level select", "Replays",
+                              "Settings", "Quit"]
+        .iter().map(|s| s.to_string()).collect();
+    loop {
+        match list(ui, "Sokobad", &items) {
+            Pick::Closed | Pick::Back => return Choice::Quit,
+            Pick::Selected(0) if !maps.is_empty() => return Choice::Play(maps[0].clone()),
+            Pick::Selected(1) if !maps.is_empty() => {
+                /* first unsolved level, or the last one */
+                let next = maps.iter().find(|m| scores.best(m).is_none())
+                    .unwrap_or(&maps[maps.len() - 1]);
+                return Choice::Play(next.clone())
+            },
+            Pick::Selected(2) => match level_select(ui, conf, scores, maps) {
+                Pick::Selected(i) => return Choice::Play(maps[i].clone()),
+                Pick::Closed => return Choice::Quit,
+                Pick::Back => {}
+            },
+            Pick::Selected(3) => {
+                if let Some(c) = replay_select(ui, maps, &conf.replays) {
+                    return c
+                }
+            },
+            Pick::Selected(4) => {
+                if settings::run(&mut ui.canvas, &mut ui.events, &ui.font,
+                                 conf, config_path) {
+                    return Choice::Quit
+                }
+            },
+            Pick::Selected(_) => return Choice::Quit
+        }
+    }
+}
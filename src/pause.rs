@@ -0,0 +1,73 @@
+extern crate sdl2;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+
+use text;
+use ui::Ui;
+
+#[derive(Copy, Clone)]
+pub enum Choice {
+    Resume,
+    Restart,
+    SaveQuit,
+    Menu,
+    Closed /* the window was closed */
+}
+
+const CHOICES: [(&str, Choice); 4] = [("Resume", Choice::Resume),
+                                      ("Restart", Choice::Restart),
+                                      ("Save and quit", Choice::SaveQuit),
+                                      ("Quit to menu", Choice::Menu)];
+
+/* Pause overlay, drawn on top of the frozen game. background redraws the
+ * game before each frame of the overlay */
+pub fn run<F: FnMut(&mut Ui)>(ui: &mut Ui, mut background: F) -> Choice {
+    let n = CHOICES.len();
+    let mut selected = 0;
+    loop {
+        for event in ui.events.poll_iter() {
+            match event {
+                Event::Quit {..} => return Choice::Closed,
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    match key {
+                        Keycode::Up => selected = (selected + n - 1) % n,
+                        Keycode::Down => selected = (selected + 1) % n,
+                        Keycode::Return => return CHOICES[selected].1,
+                        Keycode::Escape => return Choice::Resume,
+                        _ => {}
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        background(ui);
+
+        let canvas = &mut ui.canvas;
+        let font = &ui.font;
+        let (w, h) = canvas.output_size().unwrap();
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 176));
+        canvas.fill_rect(None).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        let white = Color::RGB(255, 255, 255);
+        let line = font.recommended_line_spacing();
+        let mut y = (h as i32 - (n as i32 + 2) * line) / 2;
+        text::draw_centered(canvas, font, "Paused", white, y);
+        y += 2 * line;
+        for (i, &(label, _)) in CHOICES.iter().enumerate() {
+            if i == selected {
+                canvas.set_draw_color(Color::RGB(96, 96, 96));
+                canvas.fill_rect(Rect::new(0, y, w, line as u32)).unwrap();
+            }
+            text::draw_centered(canvas, font, label, white, y);
+            y += line;
+        }
+        canvas.present();
+    }
+}
@@ -0,0 +1,469 @@
+extern crate sdl2;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{self, Keycode};
+use sdl2::mouse::{MouseButton, MouseWheelDirection};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+
+use std::collections::VecDeque;
+
+use camera;
+use config;
+use game;
+use hud;
+use pause;
+use record;
+use settings;
+use ui::{self, Ui};
+
+/* How long a refused drag target stays marked, in ms */
+const REFUSAL_DURATION: u32 = 500;
+
+pub enum Mode {
+    Play,
+    Replay(record::Run)
+}
+
+/* Why a level ended */
+#[derive(Copy, Clone, PartialEq)]
+pub enum Exit {
+    Finished, /* won, given up or replayed: show the end screen */
+    Menu, /* back to the title menu */
+    Save, /* save the run and leave the game */
+    Closed /* the window was closed: leave the game */
+}
+
+/* How a level ended */
+pub struct Outcome {
+    pub won: bool,
+    pub exit: Exit,
+    pub stats: game::Stats,
+    pub elapsed: u32, /* in ms */
+    pub movements: i32,
+    pub run: record::Run /* what was played or replayed */
+}
+
+pub fn run(ui: &mut Ui, conf: &mut config::Config, config_path: &str,
+           map: &mut game::Map, mode: Mode) -> Outcome {
+    let (replay, mut record) = match mode {
+        Mode::Play => (false, record::Run::new()),
+        Mode::Replay(r) => (true, r)
+    };
+    let played = record.replay();
+    let speed = conf.replay_speed;
+    let mut camera = camera::Camera::new(conf.cell_size, conf.camera_margin);
+
+    let mut now = ui.timer.ticks();
+    let start = now;
+    let mut paused = 0; /* time spent in the pause menu or the settings */
+    let mut show_hud = conf.hud;
+    let mut done = false;
+    let mut exit = Exit::Finished;
+    let mut movements = 0;
+    /* every command, in the order it was given, waiting for the current move
+     * animation to end */
+    let mut pending = VecDeque::new();
+    /* key held down, its command and when to repeat it */
+    let mut held: Option<(Keycode, record::Command, u32)> = None;
+    let mut anim_start: Option<u32> = None;
+    /* mouse position, and where the board was last drawn */
+    let mut hover: Option<(i32, i32)> = None;
+    let mut board = None;
+    /* block being dragged, and the last target that couldn't be reached */
+    let mut drag: Option<(i32, i32)> = None;
+    let mut refused: Option<((i32, i32), u32)> = None;
+    'main: loop {
+        let mut open_settings = false;
+        let mut open_pause = false;
+        for event in ui.events.poll_iter() {
+            if view_event(&event, &mut ui.canvas, &mut camera, &mut show_hud,
+                          conf) {
+                continue
+            }
+            if replay {
+                match event {
+                    Event::Quit {..} => {
+                        exit = Exit::Closed;
+                        break 'main
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                        break 'main
+                    },
+                    _ => {},
+                }
+            } else {
+                match event {
+                    Event::Quit {..} => {
+                        record.record(record::Command::Quit);
+                        exit = Exit::Closed;
+                        break 'main
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                        open_pause = true
+                    },
+                    Event::KeyDown { keycode: Some(key), .. }
+                        if key == conf.keys.settings => {
+                        open_settings = true
+                    },
+                    Event::MouseMotion { x, y, .. } => hover = Some((x, y)),
+                    Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                        /* paths are computed from the current state, so
+                         * clicks are ignored until the queue is empty */
+                        let idle = pending.is_empty() && anim_start.is_none() && !done;
+                        match cell_under(map, board, x, y) {
+                            Some((i, j)) if map.is_movable(i, j) => drag = Some((i, j)),
+                            Some((i, j)) if idle => {
+                                pending.extend(commands(map.walk_to(i, j)))
+                            },
+                            _ => {}
+                        }
+                    },
+                    Event::MouseButtonUp { mouse_btn: MouseButton::Left, x, y, .. } => {
+                        let idle = pending.is_empty() && anim_start.is_none() && !done;
+                        match (drag.take(), cell_under(map, board, x, y)) {
+                            (Some(from), Some(to)) if idle && from != to => {
+                                match map.push_to(from, to) {
+                                    Some(path) => pending.extend(commands(Some(path))),
+                                    None => refused = Some((to, ui.timer.ticks()))
+                                }
+                            },
+                            _ => {}
+                        }
+                    },
+                    /* holding a key is handled below, not by the system */
+                    Event::KeyDown { keycode: Some(key), keymod, repeat: false, .. } => {
+                        let running = keymod.intersects(keyboard::LSHIFTMOD |
+                                                        keyboard::RSHIFTMOD);
+                        if let Some(c) = cmd_of_key(key, &conf.keys, running) {
+                            pending.push_back(c);
+                            held = match c {
+                                record::Command::Quit | record::Command::Reset |
+                                record::Command::Switch => None,
+                                _ => Some((key, c, ui.timer.ticks() + conf.repeat_delay))
+                            }
+                        }
+                    },
+                    Event::KeyUp { keycode: Some(key), .. } => {
+                        if let Some((k, _, _)) = held {
+                            if k == key {
+                                held = None
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        if open_settings || open_pause {
+            /* the key up event goes to the other screen */
+            held = None
+        }
+
+        if open_settings {
+            let since = ui.timer.ticks();
+            if settings::run(&mut ui.canvas, &mut ui.events, &ui.font,
+                             conf, config_path) {
+                record.record(record::Command::Quit);
+                exit = Exit::Closed;
+                break 'main
+            }
+            paused += ui.timer.ticks() - since;
+        }
+
+        if open_pause {
+            let since = ui.timer.ticks();
+            let elapsed = since - start - paused;
+            let choice = pause::run(ui, |ui| {
+                draw(ui, conf, map, &mut camera, show_hud, elapsed, 1.0);
+            });
+            paused += ui.timer.ticks() - since;
+            match choice {
+                pause::Choice::Resume => {},
+                pause::Choice::Restart => {
+                    pending.clear();
+                    pending.push_back(record::Command::Reset)
+                },
+                pause::Choice::SaveQuit | pause::Choice::Menu |
+                pause::Choice::Closed => {
+                    record.record(record::Command::Quit);
+                    exit = match choice {
+                        pause::Choice::SaveQuit => Exit::Save,
+                        pause::Choice::Menu => Exit::Menu,
+                        _ => Exit::Closed
+                    };
+                    break 'main
+                }
+            }
+        }
+
+        if replay {
+            let cur = ui.timer.ticks();
+            if cur - now > speed {
+                now = cur;
+                match record.next() {
+                    Some(c) => {
+                        pending.push_back(c)
+                    },
+                    None => break 'main
+                }
+            }
+        }
+
+        /* hold to repeat, without piling up commands during animations */
+        if let Some((key, c, next)) = held {
+            let ticks = ui.timer.ticks();
+            if conf.repeat_delay > 0 && ticks >= next && pending.is_empty() {
+                pending.push_back(c);
+                held = Some((key, c, ticks + 1000 / conf.repeat_rate))
+            }
+        }
+
+        /* Moves are applied at once, the animation only changes the display.
+         * It is skipped when replaying faster than it lasts */
+        let duration = conf.animation_duration;
+        let ticks = ui.timer.ticks();
+        let mut progress = match anim_start {
+            Some(t) if ticks - t < duration => (ticks - t) as f32 / duration as f32,
+            _ => {
+                anim_start = None;
+                1.0
+            }
+        };
+
+        if anim_start.is_none() && !done {
+            if let Some(cmd) = pending.pop_front() {
+                movements += 1;
+                if !replay { record.record(cmd) };
+                let moves = map.stats().moves;
+                match cmd {
+                    record::Command::Quit => break 'main,
+                    record::Command::Up => done = map.update(game::Direction::Up),
+                    record::Command::Down => done = map.update(game::Direction::Down),
+                    record::Command::Left => done = map.update(game::Direction::Left),
+                    record::Command::Right => done = map.update(game::Direction::Right),
+                    record::Command::RunUp => done = map.run(game::Direction::Up),
+                    record::Command::RunDown => done = map.run(game::Direction::Down),
+                    record::Command::RunLeft => done = map.run(game::Direction::Left),
+                    record::Command::RunRight => done = map.run(game::Direction::Right),
+                    record::Command::Switch => {
+                        /* not a move */
+                        map.switch();
+                        movements -= 1
+                    },
+                    record::Command::Undo => {
+                        if map.undo() {
+                            /* to account for the +1 above */
+                            movements -= 2
+                        }
+                    },
+                    record::Command::Reset => {
+                        movements = 0;
+                        map.reset()
+                    }
+                }
+                let animate = duration > 0 && !(replay && speed < duration);
+                if animate && map.stats().moves > moves {
+                    anim_start = Some(ticks);
+                    progress = 0.0
+                }
+            }
+        }
+
+        let elapsed = ui.timer.ticks() - start - paused;
+        /* no walking while something is going on */
+        let idle = !replay && !done && anim_start.is_none() && pending.is_empty();
+        let (area, view) = draw(ui, conf, map, &mut camera, show_hud, elapsed,
+                                progress);
+        match (drag, hover) {
+            (Some(from), Some((x, y))) => {
+                let mut cells = vec![from];
+                cells.extend(map.cell_at(area, view, x, y));
+                shade(&mut ui.canvas, area, view, &cells, Color::RGBA(255, 255, 51, 96))
+            },
+            (None, Some(mouse)) if idle => {
+                draw_reachable(&mut ui.canvas, map, area, view, mouse)
+            },
+            _ => {}
+        }
+        if let Some((cell, since)) = refused {
+            if ui.timer.ticks() - since < REFUSAL_DURATION {
+                shade(&mut ui.canvas, area, view, &[cell], Color::RGBA(255, 0, 0, 128))
+            } else {
+                refused = None
+            }
+        }
+        board = Some((area, view));
+        ui.canvas.present();
+
+        if done && anim_start.is_none() {
+            break 'main
+        }
+    }
+
+    Outcome {
+        won: done,
+        exit,
+        stats: map.stats(),
+        elapsed: ui.timer.ticks() - start - paused,
+        movements,
+        run: if replay { played } else { record }
+    }
+}
+
+fn cell_under(map: &game::Map, board: Option<(Rect, game::View)>, x: i32, y: i32)
+              -> Option<(i32, i32)> {
+    board.and_then(|(area, view)| map.cell_at(area, view, x, y))
+}
+
+/* Commands following a path computed for the mouse, if any */
+fn commands(path: Option<Vec<game::Direction>>) -> Vec<record::Command> {
+    path.unwrap_or_default().iter().map(|d| match *d {
+        game::Direction::Up => record::Command::Up,
+        game::Direction::Down => record::Command::Down,
+        game::Direction::Left => record::Command::Left,
+        game::Direction::Right => record::Command::Right
+    }).collect()
+}
+
+/* Blend color over some cells of the board */
+fn shade(canvas: &mut sdl2::render::WindowCanvas, area: Rect, view: game::View,
+         cells: &[(i32, i32)], color: Color) {
+    let cs = view.cell as i32;
+    canvas.set_clip_rect(area);
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(color);
+    for &(i, j) in cells {
+        let r = Rect::new(view.x + i * cs, view.y + j * cs, view.cell, view.cell);
+        canvas.fill_rect(r).unwrap();
+    }
+    canvas.set_blend_mode(BlendMode::None);
+    canvas.set_clip_rect(None);
+}
+
+/* Shade the cells the player can walk to while the mouse is over one of
+ * them */
+fn draw_reachable(canvas: &mut sdl2::render::WindowCanvas, map: &game::Map,
+                  area: Rect, view: game::View, mouse: (i32, i32)) {
+    let dist = match map.cell_at(area, view, mouse.0, mouse.1) {
+        Some((i, j)) => {
+            let dist = map.distances();
+            if dist[j as usize][i as usize].is_none() {
+                return
+            }
+            dist
+        },
+        None => return
+    };
+    let mut cells = Vec::new();
+    for (j, row) in dist.iter().enumerate() {
+        for (i, d) in row.iter().enumerate() {
+            if d.is_some() {
+                cells.push((i as i32, j as i32))
+            }
+        }
+    }
+    shade(canvas, area, view, &cells, Color::RGBA(255, 255, 255, 64))
+}
+
+/* Draw the game and the HUD, without presenting it. Returns where the board
+ * was drawn */
+fn draw(ui: &mut Ui, conf: &config::Config, map: &game::Map,
+        camera: &mut camera::Camera, show_hud: bool, elapsed: u32,
+        progress: f32) -> (Rect, game::View) {
+    let canvas = &mut ui.canvas;
+    canvas.set_draw_color(conf.theme.background);
+    canvas.clear();
+
+    let (w, h) = canvas.output_size().unwrap();
+    let top = if show_hud { hud::height(&ui.font) } else { 0 };
+    let area = Rect::new(0, top as i32, w, h.saturating_sub(top).max(1));
+    let view = camera.view(map, area, conf.integer_scaling);
+    map.render(canvas, &game::Skin {
+        theme: &conf.theme,
+        glyphs: conf.shape_glyphs,
+        tileset: ui.tiles.as_ref()
+    }, area, view, progress);
+    if show_hud {
+        hud::draw(canvas, &ui.font, map, elapsed);
+    }
+    (area, view)
+}
+
+/* Events changing how the game is displayed, available while playing and
+ * replaying. Returns true if the event was handled */
+fn view_event(event: &Event, canvas: &mut sdl2::render::WindowCanvas,
+              camera: &mut camera::Camera, hud: &mut bool,
+              conf: &config::Config) -> bool {
+    let keys = &conf.keys;
+    let integer = conf.integer_scaling;
+    let step = camera.cell_size() as i32;
+    match *event {
+        Event::KeyDown { keycode: key @ Some(_), .. } => {
+            if key == keys.fullscreen {
+                ui::toggle_fullscreen(canvas)
+            } else if key == keys.hud {
+                *hud = !*hud
+            } else if key == keys.zoom_in {
+                camera.zoom_in(integer)
+            } else if key == keys.zoom_out {
+                camera.zoom_out(integer)
+            } else if key == keys.pan_up {
+                camera.pan(0, -step)
+            } else if key == keys.pan_down {
+                camera.pan(0, step)
+            } else if key == keys.pan_left {
+                camera.pan(-step, 0)
+            } else if key == keys.pan_right {
+                camera.pan(step, 0)
+            } else {
+                return false
+            }
+            true
+        },
+        /* drag with the right button to look around */
+        Event::MouseMotion { mousestate, xrel, yrel, .. } if mousestate.right() => {
+            camera.pan(-xrel, -yrel);
+            true
+        },
+        Event::MouseWheel { y, direction, .. } => {
+            let y = match direction {
+                MouseWheelDirection::Flipped => -y,
+                _ => y
+            };
+            if y > 0 {
+                camera.zoom_in(integer)
+            } else if y < 0 {
+                camera.zoom_out(integer)
+            }
+            true
+        },
+        _ => false
+    }
+}
+
+/* running: the direction keys move until blocked */
+fn cmd_of_key(key: Keycode, keys: &config::KeyBindings, running: bool)
+              -> Option<record::Command> {
+    if key == keys.quit {
+        Some(record::Command::Quit)
+    } else if key == keys.up {
+        Some(if running { record::Command::RunUp } else { record::Command::Up })
+    } else if key == keys.down {
+        Some(if running { record::Command::RunDown } else { record::Command::Down })
+    } else if key == keys.left {
+        Some(if running { record::Command::RunLeft } else { record::Command::Left })
+    } else if key == keys.right {
+        Some(if running { record::Command::RunRight } else { record::Command::Right })
+    } else if key == keys.undo {
+        Some(record::Command::Undo)
+    } else if key == keys.reset {
+        Some(record::Command::Reset)
+    } else if key == keys.switch {
+        Some(record::Command::Switch)
+    } else {
+        None
+    }
+}
@@ -9,6 +9,11 @@ pub enum Command {
     Down,
     Left,
     Right,
+    RunUp, /* move until the next step would push or bump */
+    RunDown,
+    RunLeft,
+    RunRight,
+    Switch, /* the next player takes over */
     Undo,
     Reset,
     Quit
@@ -21,6 +26,11 @@ impl fmt::Display for Command {
                 Command::Down  => write!(f, "Down"),
                 Command::Left  => write!(f, "Left"),
                 Command::Right => write!(f, "Right"),
+                Command::RunUp    => write!(f, "RunUp"),
+                Command::RunDown  => write!(f, "RunDown"),
+                Command::RunLeft  => write!(f, "RunLeft"),
+                Command::RunRight => write!(f, "RunRight"),
+                Command::Switch => write!(f, "Switch"),
                 Command::Undo  => write!(f, "Undo"),
                 Command::Reset => write!(f, "Reset"),
                 Command::Quit  => write!(f, "Quit")
@@ -28,6 +38,7 @@ impl fmt::Display for Command {
     }
 }
 
+#[derive(Clone)]
 struct State {
     cmds: Vec<Command>,
 }
@@ -65,6 +76,11 @@ impl State {
         else if line == "Down" { Ok(Command::Down) }
         else if line == "Left" { Ok(Command::Left) }
         else if line == "Right" { Ok(Command::Right) }
+        else if line == "RunUp" { Ok(Command::RunUp) }
+        else if line == "RunDown" { Ok(Command::RunDown) }
+        else if line == "RunLeft" { Ok(Command::RunLeft) }
+        else if line == "RunRight" { Ok(Command::RunRight) }
+        else if line == "Switch" { Ok(Command::Switch) }
         else if line == "Undo" { Ok(Command::Undo) }
         else if line == "Reset" { Ok(Command::Reset) }
         else if line == "Quit" { Ok(Command::Quit) }
@@ -91,17 +107,12 @@ impl State {
 }
 
 pub struct Run {
-    empty: bool,
     state: State
 }
 
 impl Run {
-    pub fn new(path: &str) -> Run {
-        println!("New run {}", path);
-        let state = State {
-            cmds: Vec::new()
-        };
-        Run { empty: false, state: state }
+    pub fn new() -> Run {
+        Run { state: State { cmds: Vec::new() } }
     }
 
     pub fn load(path: &str) -> Run {
@@ -110,34 +121,28 @@ impl Run {
             Ok(s) => s,
             Err(e) => panic!("Failure to load '{}': {}", path, e)
         };
-        Run { empty: false, state: state }
+        Run { state: state }
     }
 
     pub fn next(&mut self) -> Option<Command> {
-        if self.empty {
-            None
-        } else {
-            self.state.next()
-        }
+        self.state.next()
     }
 
-    pub fn empty() -> Run {
-        Run { empty: true, state: State { cmds: Vec::new() } }
+    /* Copy of the run, to replay it from the start */
+    pub fn replay(&self) -> Run {
+        Run { state: self.state.clone() }
     }
 
     pub fn record(&mut self, cmd: Command) {
-        if !self.empty {
-            self.state.cmds.push(cmd);
-        }
+        self.state.cmds.push(cmd);
     }
 
     pub fn save(&self, path: &str) {
-        if !self.empty {
-            match self.state.save(path) {
-                Ok(_) => (),
-                Err(e) => eprintln!("Error while saving run to '{}': {}",
-                                    path, e)
-            }
+        println!("Saving run to {}", path);
+        match self.state.save(path) {
+            Ok(_) => (),
+            Err(e) => eprintln!("Error while saving run to '{}': {}",
+                                path, e)
         }
     }
 }
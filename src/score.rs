@@ -0,0 +1,92 @@
+extern crate json;
+
+use std::collections::BTreeMap;
+use std::fs;
+
+/* Number of results kept for each level */
+const MAX_SCORES: usize = 10;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Score {
+    /* field order matters: fewer moves first, then pushes, then time */
+    pub moves: u32,
+    pub pushes: u32,
+    pub time: u32 /* in ms */
+}
+
+/* Best results for each level, indexed by map path */
+pub struct Scoreboard {
+    path: String,
+    scores: BTreeMap<String, Vec<Score>>
+}
+
+impl Scoreboard {
+    /* A missing or broken file gives an empty scoreboard */
+    pub fn load(path: &str) -> Scoreboard {
+        let mut board = Scoreboard { path: path.to_string(), scores: BTreeMap::new() };
+        let data = match fs::read_to_string(path) {
+            Ok(d) => d,
+            Err(_) => return board
+        };
+        let scores = match json::parse(&data) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("W: can't parse scoreboard '{}': {}", path, e);
+                return board
+            }
+        };
+        for (level, entries) in scores.entries() {
+            let mut list = Vec::new();
+            for e in entries.members() {
+                match (e["moves"].as_u32(), e["pushes"].as_u32(), e["time"].as_u32()) {
+                    (Some(m), Some(p), Some(t)) =>
+                        list.push(Score { moves: m, pushes: p, time: t }),
+                    _ => eprintln!("W: invalid score for '{}' in '{}'", level, path)
+                }
+            }
+            list.sort();
+            list.truncate(MAX_SCORES);
+            board.scores.insert(level.to_string(), list);
+        }
+        board
+    }
+
+    pub fn best(&self, level: &str) -> Option<Score> {
+        match self.scores.get(level) {
+            Some(list) => list.first().cloned(),
+            None => None
+        }
+    }
+
+    /* Add a result, and return its rank (starting at 1) if it made it to
+     * the scoreboard */
+    pub fn insert(&mut self, level: &str, score: Score) -> Option<usize> {
+        let list = self.scores.entry(level.to_string()).or_default();
+        /* ties go after the existing results */
+        let rank = list.iter().take_while(|&&s| s <= score).count();
+        if rank >= MAX_SCORES {
+            return None
+        }
+        list.insert(rank, score);
+        list.truncate(MAX_SCORES);
+        Some(rank + 1)
+    }
+
+    pub fn save(&self) {
+        let mut data = json::JsonValue::new_object();
+        for (level, list) in &self.scores {
+            let mut entries = json::JsonValue::new_array();
+            for s in list {
+                let mut entry = json::JsonValue::new_object();
+                entry["moves"] = s.moves.into();
+                entry["pushes"] = s.pushes.into();
+                entry["time"] = s.time.into();
+                entries.push(entry).unwrap();
+            }
+            data[level.as_str()] = entries;
+        }
+        if let Err(e) = fs::write(&self.path, format!("{}\n", data.pretty(4))) {
+            eprintln!("E: can't save scoreboard '{}': {}", self.path, e)
+        }
+    }
+}
@@ -0,0 +1,123 @@
+extern crate sdl2;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+use sdl2::EventPump;
+
+use config;
+use text;
+
+fn save(conf: &config::Config, path: &str) {
+    match conf.save(path) {
+        Ok(_) => println!("Configuration saved to '{}'", path),
+        Err(e) => eprintln!("E: {}", e)
+    }
+}
+
+/* Try to bind key to action. Returns the message to display */
+fn rebind(keys: &mut config::KeyBindings, action: &str, key: Keycode)
+          -> (String, bool) {
+    let name = match config::key_name(key) {
+        Some(n) => n,
+        None => return ("This key can't be bound".to_string(), false)
+    };
+    match keys.action_of(key) {
+        Some(other) if other != action =>
+            (format!("'{}' is already bound to '{}'", name, other), false),
+        _ => {
+            keys.set(action, key);
+            (format!("'{}' is now bound to '{}'", action, name), true)
+        }
+    }
+}
+
+/* Key rebinding screen. Changes apply immediately and are saved to the
+ * configuration file when leaving. Returns true if the window was closed */
+pub fn run(canvas: &mut WindowCanvas, events: &mut EventPump, font: &Font,
+           conf: &mut config::Config, path: &str) -> bool {
+    let n = config::ACTIONS.len();
+    let mut selected = 0;
+    let mut capture = false;
+    let mut changed = false;
+    let mut message = String::new();
+    let mut error = false;
+
+    loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit {..} => {
+                    if changed { save(conf, path) }
+                    return true
+                },
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    let action = config::ACTIONS[selected];
+                    if capture {
+                        capture = false;
+                        if key == Keycode::Escape {
+                            message.clear()
+                        } else {
+                            let (msg, ok) = rebind(&mut conf.keys, action, key);
+                            message = msg;
+                            error = !ok;
+                            changed = changed || ok
+                        }
+                    } else {
+                        match key {
+                            Keycode::Up => selected = (selected + n - 1) % n,
+                            Keycode::Down => selected = (selected + 1) % n,
+                            Keycode::Return => {
+                                capture = true;
+                                error = false;
+                                message = format!("Press a key for '{}'", action)
+                            },
+                            Keycode::Escape => {
+                                if changed { save(conf, path) }
+                                return false
+                            },
+                            _ => {}
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        let white = Color::RGB(255, 255, 255);
+        let line = font.recommended_line_spacing();
+        let (w, h) = canvas.output_size().unwrap();
+        let mut y = line;
+        text::draw_centered(canvas, font, "Key bindings", white, y);
+        y += 2 * line;
+        for (i, &action) in config::ACTIONS.iter().enumerate() {
+            if i == selected {
+                canvas.set_draw_color(if capture {
+                    Color::RGB(255, 128, 0)
+                } else {
+                    Color::RGB(96, 96, 96)
+                });
+                canvas.fill_rect(Rect::new(0, y, w, line as u32)).unwrap();
+            }
+            let key = conf.keys.get(action).and_then(config::key_name).unwrap_or("none");
+            text::draw(canvas, font, action, white, line, y);
+            text::draw(canvas, font, key, white, w as i32 / 2, y);
+            y += line;
+        }
+        let color = if error {
+            Color::RGB(255, 51, 51)
+        } else {
+            Color::RGB(255, 255, 51)
+        };
+        text::draw_centered(canvas, font, &message, color, y + line);
+        text::draw_centered(canvas, font,
+                            "Enter: rebind   Escape: back", white,
+                            h as i32 - 2 * line);
+        canvas.present();
+    }
+}
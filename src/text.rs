@@ -0,0 +1,44 @@
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+
+/* Draw a single line of text with its top left corner at (x, y).
+ * Returns the size of the rendered text */
+pub fn draw(canvas: &mut WindowCanvas, font: &Font, text: &str,
+            color: Color, x: i32, y: i32) -> (u32, u32) {
+    if text.is_empty() {
+        return (0, 0)
+    }
+    let surface = match font.render(text).blended(color) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("W: can't render '{}': {}", text, e);
+            return (0, 0)
+        }
+    };
+    let creator = canvas.texture_creator();
+    let tex = match creator.create_texture_from_surface(&surface) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("W: can't render '{}': {}", text, e);
+            return (0, 0)
+        }
+    };
+    let q = tex.query();
+    canvas.copy(&tex, None, Some(Rect::new(x, y, q.width, q.height))).unwrap();
+    (q.width, q.height)
+}
+
+/* Same as draw, horizontally centered on the canvas */
+pub fn draw_centered(canvas: &mut WindowCanvas, font: &Font, text: &str,
+                     color: Color, y: i32) -> (u32, u32) {
+    let (w, _) = canvas.output_size().unwrap();
+    let tw = match font.size_of(text) {
+        Ok((tw, _)) => tw,
+        Err(_) => 0
+    };
+    draw(canvas, font, text, color, (w as i32 - tw as i32) / 2, y)
+}
@@ -0,0 +1,293 @@
+extern crate json;
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use std::fs;
+
+use config::ConfigError;
+
+const THEME_KEYS: [&str; 3] = ["base", "file", "colors"];
+const COLOR_KEYS: [&str; 17] = ["background", "floor", "wall", "block",
+                                "block-on-goal", "crate", "goal",
+                                "exit-closed", "exit-open", "player", "ice",
+                                "plate", "gate-closed", "gate-open", "hole",
+                                "teleport", "arrow"];
+
+#[derive(Copy, Clone)]
+pub struct Theme {
+    pub background: Color,
+    pub floor: Color,
+    pub wall: Color,
+    pub block: Color,
+    pub block_on_goal: Color,
+    pub crate_: Color,
+    pub goal: Color,
+    pub exit_closed: Color,
+    pub exit_open: Color,
+    pub player: Color,
+    pub ice: Color,
+    pub plate: Color,
+    pub gate_closed: Color,
+    pub gate_open: Color,
+    pub hole: Color,
+    pub teleport: Color,
+    pub arrow: Color,
+}
+
+impl Theme {
+    fn set(&mut self, name: &str, color: Color) {
+        match name {
+            "background" => self.background = color,
+            "floor" => self.floor = color,
+            "wall" => self.wall = color,
+            "block" => self.block = color,
+            "block-on-goal" => self.block_on_goal = color,
+            "crate" => self.crate_ = color,
+            "goal" => self.goal = color,
+            "exit-closed" => self.exit_closed = color,
+            "exit-open" => self.exit_open = color,
+            "player" => self.player = color,
+            "ice" => self.ice = color,
+            "plate" => self.plate = color,
+            "gate-closed" => self.gate_closed = color,
+            "gate-open" => self.gate_open = color,
+            "hole" => self.hole = color,
+            "teleport" => self.teleport = color,
+            "arrow" => self.arrow = color,
+            _ => panic!("Unknown theme color: {}", name)
+        }
+    }
+}
+
+pub fn builtin(name: &str) -> Option<Theme> {
+    match name {
+        "default" => Some(Theme {
+            background: Color::RGB(0, 0, 0),
+            floor: Color::RGB(192, 192, 192),
+            wall: Color::RGB(96, 96, 96),
+            block: Color::RGB(102, 51, 0),
+            block_on_goal: Color::RGB(103, 240, 139),
+            crate_: Color::RGB(255, 128, 0),
+            goal: Color::RGB(255, 255, 51),
+            exit_closed: Color::RGB(0, 0, 0),
+            exit_open: Color::RGB(255, 255, 255),
+            player: Color::RGB(255, 51, 51),
+            ice: Color::RGB(190, 230, 255),
+            plate: Color::RGB(150, 150, 170),
+            gate_closed: Color::RGB(70, 70, 110),
+            gate_open: Color::RGB(170, 170, 200),
+            hole: Color::RGB(40, 20, 0),
+            teleport: Color::RGB(120, 60, 160),
+            arrow: Color::RGB(90, 140, 90),
+        }),
+        "high-contrast" => Some(Theme {
+            background: Color::RGB(0, 0, 0),
+            floor: Color::RGB(0, 0, 0),
+            wall: Color::RGB(255, 255, 255),
+            block: Color::RGB(0, 64, 255),
+            block_on_goal: Color::RGB(0, 255, 0),
+            crate_: Color::RGB(255, 0, 255),
+            goal: Color::RGB(255, 255, 0),
+            exit_closed: Color::RGB(128, 0, 0),
+            exit_open: Color::RGB(0, 255, 255),
+            player: Color::RGB(255, 0, 0),
+            ice: Color::RGB(0, 96, 128),
+            plate: Color::RGB(128, 128, 128),
+            gate_closed: Color::RGB(0, 0, 255),
+            gate_open: Color::RGB(0, 0, 96),
+            hole: Color::RGB(96, 48, 0),
+            teleport: Color::RGB(255, 0, 255),
+            arrow: Color::RGB(0, 160, 0),
+        }),
+        "dark" => Some(Theme {
+            background: Color::RGB(0, 0, 0),
+            floor: Color::RGB(48, 48, 56),
+            wall: Color::RGB(20, 20, 24),
+            block: Color::RGB(120, 80, 40),
+            block_on_goal: Color::RGB(60, 160, 90),
+            crate_: Color::RGB(170, 95, 30),
+            goal: Color::RGB(150, 140, 40),
+            exit_closed: Color::RGB(0, 0, 0),
+            exit_open: Color::RGB(200, 200, 220),
+            player: Color::RGB(210, 60, 60),
+            ice: Color::RGB(60, 90, 120),
+            plate: Color::RGB(80, 80, 96),
+            gate_closed: Color::RGB(40, 40, 72),
+            gate_open: Color::RGB(64, 64, 88),
+            hole: Color::RGB(8, 8, 8),
+            teleport: Color::RGB(72, 40, 96),
+            arrow: Color::RGB(48, 72, 48),
+        }),
+        _ => None
+    }
+}
+
+/* Named colors pairing things in maps, such as keys and doors */
+pub const PALETTE: [(&str, (u8, u8, u8)); 8] = [("red", (220, 50, 50)),
+                                                ("green", (40, 180, 60)),
+                                                ("blue", (50, 100, 230)),
+                                                ("yellow", (230, 210, 40)),
+                                                ("cyan", (40, 200, 210)),
+                                                ("magenta", (210, 60, 200)),
+                                                ("orange", (240, 140, 30)),
+                                                ("purple", (130, 70, 200))];
+
+pub fn palette_index(name: &str) -> Option<usize> {
+    PALETTE.iter().position(|&(n, _)| n == name)
+}
+
+pub fn palette(i: usize) -> Color {
+    let (r, g, b) = PALETTE[i].1;
+    Color::RGB(r, g, b)
+}
+
+/* base color tinted with a palette color */
+pub fn tint(base: Color, i: usize) -> Color {
+    let (r, g, b) = PALETTE[i].1;
+    Color::RGB(((base.r as u32 + 2 * r as u32) / 3) as u8,
+               ((base.g as u32 + 2 * g as u32) / 3) as u8,
+               ((base.b as u32 + 2 * b as u32) / 3) as u8)
+}
+
+fn invalid_color(field: &str, value: &json::JsonValue) -> ConfigError {
+    ConfigError::InvalidValue {
+        field: field.to_string(),
+        expected: "a \"#rrggbb\" string or a [r, g, b] array",
+        found: value.dump()
+    }
+}
+
+fn parse_color(field: &str, value: &json::JsonValue) -> Result<Color, ConfigError> {
+    if let Some(s) = value.as_str() {
+        if s.len() != 7 || !s.is_ascii() || !s.starts_with('#') {
+            return Err(invalid_color(field, value))
+        }
+        let component = |i: usize| u8::from_str_radix(&s[i..i + 2], 16);
+        match (component(1), component(3), component(5)) {
+            (Ok(r), Ok(g), Ok(b)) => Ok(Color::RGB(r, g, b)),
+            _ => Err(invalid_color(field, value))
+        }
+    } else if value.is_array() && value.len() == 3 {
+        match (value[0].as_u8(), value[1].as_u8(), value[2].as_u8()) {
+            (Some(r), Some(g), Some(b)) => Ok(Color::RGB(r, g, b)),
+            _ => Err(invalid_color(field, value))
+        }
+    } else {
+        Err(invalid_color(field, value))
+    }
+}
+
+fn read_file(path: &str) -> Result<json::JsonValue, ConfigError> {
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => return Err(ConfigError::Io {
+            path: path.to_string(),
+            reason: format!("{}", e)
+        })
+    };
+    match json::parse(&data) {
+        Ok(d) => Ok(d),
+        Err(e) => Err(ConfigError::Parse {
+            path: path.to_string(),
+            reason: format!("{}", e)
+        })
+    }
+}
+
+fn builtin_from_json(value: &json::JsonValue, field: &str)
+                     -> Result<Theme, ConfigError> {
+    let theme = match value.as_str() {
+        Some(name) => builtin(name),
+        None => None
+    };
+    match theme {
+        Some(t) => Ok(t),
+        None => Err(ConfigError::InvalidValue {
+            field: field.to_string(),
+            expected: "\"default\", \"high-contrast\" or \"dark\"",
+            found: value.dump()
+        })
+    }
+}
+
+/* Apply a theme object on top of theme: the "base" built-in theme
+ * replaces it, then the theme file (same format, minus "file") and the
+ * "colors" overrides are applied in that order */
+fn apply(mut theme: Theme, value: &json::JsonValue, field: &str,
+         warnings: &mut Vec<ConfigError>) -> Result<Theme, ConfigError> {
+    if !value.is_object() {
+        return Err(ConfigError::InvalidType {
+            field: field.to_string(),
+            expected: "a theme name or an object",
+            found: value.dump()
+        })
+    }
+    for (key, _) in value.entries() {
+        if !THEME_KEYS.contains(&key) {
+            warnings.push(ConfigError::UnknownKey {
+                field: format!("{}.{}", field, key)
+            })
+        }
+    }
+
+    let base = &value["base"];
+    if !base.is_null() {
+        theme = builtin_from_json(base, &format!("{}.base", field))?
+    }
+
+    let file = &value["file"];
+    if !file.is_null() {
+        let path = match file.as_str() {
+            Some(p) => p,
+            None => return Err(ConfigError::InvalidType {
+                field: format!("{}.file", field),
+                expected: "a path",
+                found: file.dump()
+            })
+        };
+        let data = read_file(path)?;
+        if data.has_key("file") {
+            return Err(ConfigError::InvalidValue {
+                field: format!("{}.file", path),
+                expected: "no nested theme file",
+                found: data["file"].dump()
+            })
+        }
+        theme = apply(theme, &data, path, warnings)?
+    }
+
+    let colors = &value["colors"];
+    if colors.is_null() {
+        return Ok(theme)
+    }
+    if !colors.is_object() {
+        return Err(ConfigError::InvalidType {
+            field: format!("{}.colors", field),
+            expected: "an object",
+            found: colors.dump()
+        })
+    }
+    for (key, color) in colors.entries() {
+        let name = format!("{}.colors.{}", field, key);
+        if COLOR_KEYS.contains(&key) {
+            theme.set(key, parse_color(&name, color)?)
+        } else {
+            warnings.push(ConfigError::UnknownKey { field: name })
+        }
+    }
+    Ok(theme)
+}
+
+/* The "theme" entry is either the name of a built-in theme or an object
+ * like { "base": "dark", "file": "my.theme", "colors": { "wall": "#101010" } } */
+pub fn from_json(value: &json::JsonValue, field: &str,
+                 warnings: &mut Vec<ConfigError>) -> Result<Theme, ConfigError> {
+    let default = builtin("default").unwrap();
+    if value.is_null() {
+        Ok(default)
+    } else if value.is_string() {
+        builtin_from_json(value, field)
+    } else {
+        apply(default, value, field, warnings)
+    }
+}
@@ -0,0 +1,141 @@
+extern crate json;
+extern crate sdl2;
+
+use sdl2::image::LoadTexture;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use config::ConfigError;
+
+const DESCRIPTOR_KEYS: [&str; 3] = ["image", "tile-size", "tiles"];
+pub const TILE_NAMES: [&str; 22] = ["floor", "wall", "block", "block-on-goal",
+                                    "crate", "goal", "exit-closed", "exit-open",
+                                    "player-up", "player-down", "player-left",
+                                    "player-right", "ice", "plate", "gate-closed",
+                                    "gate-open", "hole", "teleport", "arrow-up",
+                                    "arrow-down", "arrow-left", "arrow-right"];
+
+/* Tileset descriptor: where the atlas is, and where each tile is in it.
+ * Tiles are given as [column, row] in tile units. Missing tiles are
+ * drawn with the theme colors instead */
+pub struct Descriptor {
+    image: PathBuf,
+    tiles: HashMap<&'static str, Rect>,
+}
+
+pub fn load_descriptor(path: &str, warnings: &mut Vec<ConfigError>)
+                       -> Result<Descriptor, ConfigError> {
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => return Err(ConfigError::Io {
+            path: path.to_string(),
+            reason: format!("{}", e)
+        })
+    };
+    let desc = match json::parse(&data) {
+        Ok(d) => d,
+        Err(e) => return Err(ConfigError::Parse {
+            path: path.to_string(),
+            reason: format!("{}", e)
+        })
+    };
+    for (key, _) in desc.entries() {
+        if !DESCRIPTOR_KEYS.contains(&key) {
+            warnings.push(ConfigError::UnknownKey {
+                field: format!("{}:{}", path, key)
+            })
+        }
+    }
+
+    let image = match desc["image"].as_str() {
+        Some(i) => i,
+        None => return Err(ConfigError::InvalidType {
+            field: format!("{}:image", path),
+            expected: "a path",
+            found: desc["image"].dump()
+        })
+    };
+    /* the image path is relative to the descriptor */
+    let image = match Path::new(path).parent() {
+        Some(dir) => dir.join(image),
+        None => PathBuf::from(image)
+    };
+
+    let size = match desc["tile-size"].as_u32() {
+        Some(s) if s > 0 => s,
+        _ => return Err(ConfigError::InvalidValue {
+            field: format!("{}:tile-size", path),
+            expected: "a positive integer",
+            found: desc["tile-size"].dump()
+        })
+    };
+
+    let entries = &desc["tiles"];
+    if !entries.is_object() {
+        return Err(ConfigError::InvalidType {
+            field: format!("{}:tiles", path),
+            expected: "an object",
+            found: entries.dump()
+        })
+    }
+    let mut tiles = HashMap::new();
+    for (key, pos) in entries.entries() {
+        let field = format!("{}:tiles.{}", path, key);
+        let name = match TILE_NAMES.iter().find(|&&n| n == key) {
+            Some(&n) => n,
+            None => {
+                warnings.push(ConfigError::UnknownKey { field });
+                continue
+            }
+        };
+        match (pos.len(), pos[0].as_u32(), pos[1].as_u32()) {
+            (2, Some(x), Some(y)) if pos.is_array() => {
+                tiles.insert(name, Rect::new((x * size) as i32, (y * size) as i32,
+                                             size, size));
+            },
+            _ => return Err(ConfigError::InvalidValue {
+                field,
+                expected: "a [column, row] array",
+                found: pos.dump()
+            })
+        }
+    }
+    Ok(Descriptor { image, tiles })
+}
+
+pub struct Tileset<'r> {
+    texture: Texture<'r>,
+    tiles: HashMap<&'static str, Rect>,
+}
+
+impl<'r> Tileset<'r> {
+    pub fn new(creator: &'r TextureCreator<WindowContext>, desc: &Descriptor)
+               -> Result<Tileset<'r>, String> {
+        let texture = match creator.load_texture(&desc.image) {
+            Ok(t) => t,
+            Err(e) => return Err(format!("Failure to load tileset '{}': {}",
+                                         desc.image.display(), e))
+        };
+        Ok(Tileset { texture, tiles: desc.tiles.clone() })
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.tiles.contains_key(name)
+    }
+
+    /* Returns false if the tileset has no such tile */
+    pub fn draw(&self, canvas: &mut WindowCanvas, name: &str, dst: Rect) -> bool {
+        match self.tiles.get(name) {
+            Some(&src) => {
+                canvas.copy(&self.texture, Some(src), Some(dst)).unwrap();
+                true
+            },
+            None => false
+        }
+    }
+}
@@ -0,0 +1,29 @@
+extern crate sdl2;
+
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::ttf::Font;
+use sdl2::video::FullscreenType;
+use sdl2::{EventPump, TimerSubsystem};
+
+use tileset::Tileset;
+
+/* SDL resources shared by all the screens */
+pub struct Ui<'ttf, 'r> {
+    pub canvas: WindowCanvas,
+    pub events: EventPump,
+    pub timer: TimerSubsystem,
+    pub font: Font<'ttf, 'static>,
+    pub tiles: Option<Tileset<'r>>,
+    pub win: Texture<'r>
+}
+
+pub fn toggle_fullscreen(canvas: &mut WindowCanvas) {
+    let window = canvas.window_mut();
+    let state = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off
+    };
+    if let Err(e) = window.set_fullscreen(state) {
+        eprintln!("W: can't toggle fullscreen: {}", e)
+    }
+}